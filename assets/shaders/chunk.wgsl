@@ -0,0 +1,28 @@
+#import bevy_pbr::{
+    pbr_fragment::pbr_input_from_standard_material,
+    pbr_functions::{alpha_discard, apply_pbr_lighting, main_pass_post_lighting_processing},
+    forward_io::{VertexOutput, FragmentOutput},
+}
+
+@group(#{MATERIAL_BIND_GROUP}) @binding(100) var<uniform> tile_size: f32;
+
+@fragment
+fn fragment(
+    in: VertexOutput,
+    @builtin(front_facing) is_front: bool,
+) -> FragmentOutput {
+    // uv counts blocks across the face and uv_b holds the atlas tile origin,
+    // wrapping uv keeps merged faces repeating the same tile
+    var atlas_in = in;
+#ifdef VERTEX_UVS_B
+    atlas_in.uv = in.uv_b + fract(in.uv) * tile_size;
+#endif
+
+    var pbr_input = pbr_input_from_standard_material(atlas_in, is_front);
+    pbr_input.material.base_color = alpha_discard(pbr_input.material, pbr_input.material.base_color);
+
+    var out: FragmentOutput;
+    out.color = apply_pbr_lighting(pbr_input);
+    out.color = main_pass_post_lighting_processing(pbr_input, out.color);
+    return out;
+}
@@ -0,0 +1,39 @@
+#import bevy_pbr::{
+    pbr_prepass_functions::{prepass_alpha_discard, calculate_motion_vector},
+    prepass_io::{VertexOutput, FragmentOutput},
+}
+
+@group(#{MATERIAL_BIND_GROUP}) @binding(100) var<uniform> tile_size: f32;
+
+// Remaps uv into the faces atlas tile like the main pass, so depth and shadows cut out the same pixels
+fn atlas_input(in: VertexOutput) -> VertexOutput {
+    var atlas_in = in;
+#ifdef VERTEX_UVS_B
+    atlas_in.uv = in.uv_b + fract(in.uv) * tile_size;
+#endif
+    return atlas_in;
+}
+
+#ifdef PREPASS_FRAGMENT
+@fragment
+fn fragment(in: VertexOutput) -> FragmentOutput {
+    prepass_alpha_discard(atlas_input(in));
+
+    var out: FragmentOutput;
+#ifdef UNCLIPPED_DEPTH_ORTHO_EMULATION
+    out.frag_depth = in.unclipped_depth;
+#endif
+#ifdef NORMAL_PREPASS
+    out.normal = vec4(normalize(in.world_normal) * 0.5 + vec3(0.5), 1.0);
+#endif
+#ifdef MOTION_VECTOR_PREPASS
+    out.motion_vector = calculate_motion_vector(in.world_position, in.previous_world_position);
+#endif
+    return out;
+}
+#else
+@fragment
+fn fragment(in: VertexOutput) {
+    prepass_alpha_discard(atlas_input(in));
+}
+#endif
@@ -0,0 +1,238 @@
+use fastnoise_lite::{FastNoiseLite, NoiseType};
+use bevy::prelude::*;
+use glam::IVec3;
+
+use crate::structure::*;
+
+
+// Offsets added to the world seed so the climate maps differ from the terrain noise
+const TEMPERATURE_SEED_OFFSET: i32 = 7013;
+const HUMIDITY_SEED_OFFSET: i32 = 8069;
+
+// Climate maps change slowly so biomes span many chunks
+const TEMPERATURE_FREQUENCY: f32 = 0.0022;
+const HUMIDITY_FREQUENCY: f32 = 0.0027;
+
+// Climate distance over which neighbouring biome heights fade into each other
+const BIOME_BLEND_WIDTH: f32 = 0.18;
+
+
+// Every biome world generation can pick, selected by temperature and humidity
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Biome {
+    Plains,
+    Forest,
+    Desert,
+    Mountains,
+    Tundra,
+}
+
+
+// Climate a biome is centred on, the shape of its terrain, the blocks covering it and the
+// features placed on it with their chance per FEATURE_CHANCE_SCALE columns, and the color that paints it on a biome map
+pub struct BiomeProfile {
+    pub name: &'static str,
+    pub temperature: f32,
+    pub humidity: f32,
+    pub base_height: f32,
+    pub height_amplitude: f32,
+    pub height_exponent: f32,
+    pub surface: &'static str,
+    pub subsurface: &'static str,
+    pub subsurface_depth: i32,
+    pub features: &'static [(Feature, u32)],
+    pub map_color: [u8; 3],
+}
+
+
+// Surface height of a column together with the biome deciding its blocks
+pub struct BiomeColumn {
+    pub biome: Biome,
+    pub height: i32,
+}
+
+
+// Temperature and humidity noise maps that place biomes in the world
+pub struct BiomeMap {
+    temperature: FastNoiseLite,
+    humidity: FastNoiseLite,
+}
+
+
+impl Biome {
+    pub const ALL: [Biome; 5] = [
+        Biome::Plains,
+        Biome::Forest,
+        Biome::Desert,
+        Biome::Mountains,
+        Biome::Tundra,
+    ];
+
+
+    /// Returns the climate, terrain shape and blocks of the biome.
+    pub fn profile(self) -> &'static BiomeProfile {
+        match self {
+            Biome::Plains => &BiomeProfile {
+                name: "Plains",
+                temperature: 0.2,
+                humidity: 0.0,
+                base_height: 8.0,
+                height_amplitude: 14.0,
+                height_exponent: 1.0,
+                surface: "grass",
+                subsurface: "dirt",
+                subsurface_depth: 3,
+                features: &[(Feature::Tree, 20), (Feature::Boulder, 10), (Feature::Ruin, 3)],
+                map_color: [112, 176, 64],
+            },
+            Biome::Forest => &BiomeProfile {
+                name: "Forest",
+                temperature: 0.1,
+                humidity: 0.45,
+                base_height: 10.0,
+                height_amplitude: 24.0,
+                height_exponent: 1.2,
+                surface: "grass",
+                subsurface: "dirt",
+                subsurface_depth: 4,
+                features: &[(Feature::Tree, 250), (Feature::Boulder, 5)],
+                map_color: [32, 104, 32],
+            },
+            Biome::Desert => &BiomeProfile {
+                name: "Desert",
+                temperature: 0.5,
+                humidity: -0.45,
+                base_height: 6.0,
+                height_amplitude: 12.0,
+                height_exponent: 1.5,
+                surface: "sand",
+                subsurface: "sandstone",
+                subsurface_depth: 4,
+                features: &[(Feature::Ruin, 6)],
+                map_color: [224, 208, 128],
+            },
+            Biome::Mountains => &BiomeProfile {
+                name: "Mountains",
+                temperature: -0.15,
+                humidity: -0.35,
+                base_height: 16.0,
+                height_amplitude: 90.0,
+                height_exponent: 2.2,
+                surface: "stone",
+                subsurface: "stone",
+                subsurface_depth: 0,
+                features: &[(Feature::Boulder, 40), (Feature::Tree, 5)],
+                map_color: [128, 128, 128],
+            },
+            Biome::Tundra => &BiomeProfile {
+                name: "Snowy Tundra",
+                temperature: -0.5,
+                humidity: 0.15,
+                base_height: 8.0,
+                height_amplitude: 10.0,
+                height_exponent: 1.0,
+                surface: "snow",
+                subsurface: "dirt",
+                subsurface_depth: 3,
+                features: &[(Feature::Boulder, 8)],
+                map_color: [240, 240, 240],
+            },
+        }
+    }
+
+
+    /// Returns the biome whose map color is closest to the given color.
+    pub fn from_map_color(color: [u8; 3]) -> Biome {
+        let color_distance = |biome: &Biome| {
+            let map_color = biome.profile().map_color;
+            (0..3).map(|channel| (map_color[channel] as i32 - color[channel] as i32).pow(2)).sum::<i32>()
+        };
+
+        Biome::ALL.into_iter().min_by_key(color_distance).unwrap()
+    }
+}
+
+
+impl BiomeProfile {
+    /// Returns the surface height this biome gives a column with the given terrain noise value.
+    fn height(&self, terrain_noise: f32) -> f32 {
+        let terrain = ((terrain_noise + 1.0) * 0.5).clamp(0.0, 1.0);
+        self.base_height + self.height_amplitude * terrain.powf(self.height_exponent)
+    }
+
+
+    /// Returns the distance between the biomes climate and the given climate.
+    fn climate_distance(&self, temperature: f32, humidity: f32) -> f32 {
+        Vec2::new(self.temperature - temperature, self.humidity - humidity).length()
+    }
+}
+
+
+impl BiomeMap {
+    /// Seeds the climate maps from the world seed.
+    pub fn new(seed: u32) -> Self {
+        let noise = |offset: i32, frequency: f32| {
+            let mut noise = FastNoiseLite::new();
+            noise.set_noise_type(Some(NoiseType::OpenSimplex2));
+            noise.set_seed(Some((seed as i32).wrapping_add(offset)));
+            noise.set_frequency(Some(frequency));
+            noise
+        };
+
+        Self {
+            temperature: noise(TEMPERATURE_SEED_OFFSET, TEMPERATURE_FREQUENCY),
+            humidity: noise(HUMIDITY_SEED_OFFSET, HUMIDITY_FREQUENCY),
+        }
+    }
+
+
+    /// Returns the temperature and humidity of a column.
+    fn climate(&self, world_x: f32, world_z: f32) -> (f32, f32) {
+        (
+            self.temperature.get_noise_2d(world_x, world_z),
+            self.humidity.get_noise_2d(world_x, world_z),
+        )
+    }
+
+
+    /// Returns the biome whose climate is closest to the climate at a world position.
+    pub fn biome_at(&self, block_position: IVec3) -> Biome {
+        let (temperature, humidity) = self.climate(block_position.x as f32, block_position.z as f32);
+        closest_biome(temperature, humidity)
+    }
+
+
+    /// Returns the biome of a column and its surface height, blending the heights of biomes with similar climates.
+    pub fn blended_height(&self, world_x: f32, world_z: f32, terrain_noise: f32) -> (Biome, f32) {
+        let (temperature, humidity) = self.climate(world_x, world_z);
+        let biome = closest_biome(temperature, humidity);
+        let closest_distance = biome.profile().climate_distance(temperature, humidity);
+
+        // Biomes nearly as close in climate as the chosen one pull the height towards their own
+        let mut total_weight = 0.0;
+        let mut blended_height = 0.0;
+        for other in Biome::ALL {
+            let profile = other.profile();
+            let excess_distance = profile.climate_distance(temperature, humidity) - closest_distance;
+            let weight = (1.0 - excess_distance / BIOME_BLEND_WIDTH).max(0.0);
+
+            total_weight += weight;
+            blended_height += weight * profile.height(terrain_noise);
+        }
+
+        (biome, blended_height / total_weight)
+    }
+}
+
+
+/// Returns the biome whose climate centre is closest to the given climate.
+fn closest_biome(temperature: f32, humidity: f32) -> Biome {
+    Biome::ALL
+        .into_iter()
+        .min_by(|first, second| {
+            let first_distance = first.profile().climate_distance(temperature, humidity);
+            let second_distance = second.profile().climate_distance(temperature, humidity);
+            first_distance.total_cmp(&second_distance)
+        })
+        .unwrap()
+}
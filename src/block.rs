@@ -1,22 +1,19 @@
 use bitflags::bitflags;
-use rand::Rng;
 
 
-// Defines the blocks type
-#[derive(Clone, Copy)]
-pub struct Block {
-    pub block_type: BlockType,
-}
+// Numeric block id, properties are looked up in the block registry
+pub type BlockId = u16;
+
 
+// Atlas tile column and row
+pub type AtlasTile = (u32, u32);
 
-// Defines each block type
-#[derive(Clone, Copy)]
-pub enum BlockType {
-    Air,
-    Grass,
-    Dirt,
-    Stone,
-    Coal,
+
+// Defines the blocks type and its per block state, such as a fluids flow level
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub struct Block {
+    pub id: BlockId,
+    pub state: u8,
 }
 
 
@@ -35,65 +32,33 @@ bitflags! {
 
 
 impl Block {
+    pub const AIR: Block = Block { id: 0, state: 0 };
+
+
     /// Sets default block to air.
     pub fn default() -> Self {
-        Self {
-            block_type: BlockType::Air,
-        }
+        Self::AIR
     }
-    
-
-    /// Returns block solidity.
-    pub fn is_solid(&self) -> bool {
-        match self.block_type {
-            BlockType::Air => false,
-            _ => true,
-        }
+
+
+    /// Returns the same block type with a different state.
+    pub fn with_state(self, state: u8) -> Self {
+        Self { state, ..self }
     }
 }
 
 
 const ATLAS_SIZE: f32 = 1.0 / 16.0;
 const PADDING: f32 = 0.5 / 1024.0;
+pub const TILE_SIZE: f32 = ATLAS_SIZE - 2.0 * PADDING;
 
 
-impl BlockType {
-    /// Gets texture uv grid location
-    pub fn get_texture(&self) -> [[f32; 2]; 4] {
-        let (texture_x, texture_y) = match self {
-            BlockType::Grass => (0, 0),
-            BlockType::Dirt  => (2, 0),
-            BlockType::Stone => (1, 0),
-            BlockType::Coal  => (2, 2),
-            _ => (0, 0),
-        };
-
-        let u_min = texture_x as f32 * ATLAS_SIZE + PADDING;
-        let v_min = texture_y as f32 * ATLAS_SIZE + PADDING;
-        let u_max = u_min + ATLAS_SIZE - 2.0 * PADDING;
-        let v_max = v_min + ATLAS_SIZE - 2.0 * PADDING;
-
-        [
-            [u_min, v_min],
-            [u_max, v_min],
-            [u_max, v_max],
-            [u_min, v_max],
-        ]
-    }
-
-
-    /// Returns spawn chance of each rare block
-    pub fn get_chance(block_type: BlockType) -> bool {
-        let mut rng = rand::thread_rng();
-        let generate = rng.gen_range(0..100);
-
-        let chance = match block_type {
-            BlockType::Coal => 5,   
-            _ => 0,          
-        };
-
-        generate < chance
-    }
+/// Gets the uv origin of a texture atlas tile
+pub fn get_tile_origin((texture_x, texture_y): AtlasTile) -> [f32; 2] {
+    [
+        texture_x as f32 * ATLAS_SIZE + PADDING,
+        texture_y as f32 * ATLAS_SIZE + PADDING,
+    ]
 }
 
 
@@ -161,30 +126,31 @@ pub const INDICES: [u32; 6] = [
 ];
 
 
-/// Offsets face vertices by block position within the chunk.
+// Block indices split along the other diagonal
+pub const FLIPPED_INDICES: [u32; 6] = [
+    1, 2, 3, 1, 3, 0
+];
+
+
+// Face uvs in tile space, scaled by the face size when meshing, v points down the texture
+pub const FACE_UVS: [[f32; 2]; 4] = [
+    [0.0, 1.0], [1.0, 1.0],
+    [1.0, 0.0], [0.0, 0.0],
+];
+
+
+/// Scales face vertices to the face size and offsets them by block position within the chunk.
 pub fn offset_vertices(
     face_vertices: &[[f32; 3]; 4], 
     block_offset: [f32; 3], 
+    face_size: [f32; 3],
     chunk_vertices: &mut Vec<[f32; 3]>,
 ) {
-    chunk_vertices.push([
-        face_vertices[0][0] + block_offset[0],
-        face_vertices[0][1] + block_offset[1],
-        face_vertices[0][2] + block_offset[2],
-    ]);
-    chunk_vertices.push([
-        face_vertices[1][0] + block_offset[0],
-        face_vertices[1][1] + block_offset[1],
-        face_vertices[1][2] + block_offset[2],
-    ]);
-    chunk_vertices.push([
-        face_vertices[2][0] + block_offset[0],
-        face_vertices[2][1] + block_offset[1],
-        face_vertices[2][2] + block_offset[2],
-    ]);
-    chunk_vertices.push([
-        face_vertices[3][0] + block_offset[0],
-        face_vertices[3][1] + block_offset[1],
-        face_vertices[3][2] + block_offset[2],
-    ]);
-}
\ No newline at end of file
+    for vertex in face_vertices {
+        chunk_vertices.push([
+            vertex[0] * face_size[0] + block_offset[0],
+            vertex[1] * face_size[1] + block_offset[1],
+            vertex[2] * face_size[2] + block_offset[2],
+        ]);
+    }
+}
@@ -0,0 +1,190 @@
+use fastnoise_lite::{FastNoiseLite, NoiseType};
+use bevy::prelude::*;
+use glam::IVec3;
+
+use crate::chunk::*;
+use crate::block::*;
+
+
+// Offsets added to the world seed so each carver gets its own noise field
+const CHEESE_SEED_OFFSET: i32 = 1013;
+const SPAGHETTI_SEED_OFFSETS: [i32; 2] = [2027, 3041];
+const RAVINE_SEED_OFFSET: i32 = 4057;
+const RAVINE_MASK_SEED_OFFSET: i32 = 5081;
+
+// Vertical noise coordinates are stretched so caves come out wider than they are tall
+const CAVE_VERTICAL_STRETCH: f32 = 1.6;
+
+// Frequency and cutoff of the noise that decides where ravines may open
+const RAVINE_MASK_FREQUENCY: f32 = 0.004;
+const RAVINE_MASK_THRESHOLD: f32 = 0.3;
+
+
+// Frequencies and thresholds of the noise carvers that hollow out the terrain after the heightmap pass
+#[derive(Clone, Copy)]
+pub struct CaveSettings {
+    pub min_depth: i32,
+    pub cheese_frequency: f32,
+    pub cheese_threshold: f32,
+    pub spaghetti_frequency: f32,
+    pub spaghetti_width: f32,
+    pub ravines: bool,
+    pub ravine_frequency: f32,
+    pub ravine_width: f32,
+    pub ravine_depth: i32,
+}
+
+
+// Noise fields sampled by the carvers, built once per chunk
+struct CaveNoise {
+    cheese: FastNoiseLite,
+    spaghetti: [FastNoiseLite; 2],
+    ravine: FastNoiseLite,
+    ravine_mask: FastNoiseLite,
+}
+
+
+// Carver noise of a seed together with its settings, shared by carving and by features checking their ground
+pub struct CaveCarver {
+    noise: CaveNoise,
+    settings: CaveSettings,
+}
+
+
+impl CaveSettings {
+    /// Sets default cave settings.
+    pub fn default() -> Self {
+        Self {
+            min_depth: 5,
+            cheese_frequency: 0.018,
+            cheese_threshold: 0.6,
+            spaghetti_frequency: 0.025,
+            spaghetti_width: 0.07,
+            ravines: true,
+            ravine_frequency: 0.006,
+            ravine_width: 0.03,
+            ravine_depth: 40,
+        }
+    }
+}
+
+
+impl CaveNoise {
+    /// Seeds every carver noise from the world seed.
+    fn new(seed: u32, settings: &CaveSettings) -> Self {
+        let noise = |offset: i32, frequency: f32| {
+            let mut noise = FastNoiseLite::new();
+            noise.set_noise_type(Some(NoiseType::OpenSimplex2));
+            noise.set_seed(Some((seed as i32).wrapping_add(offset)));
+            noise.set_frequency(Some(frequency));
+            noise
+        };
+
+        Self {
+            cheese: noise(CHEESE_SEED_OFFSET, settings.cheese_frequency),
+            spaghetti: SPAGHETTI_SEED_OFFSETS.map(|offset| noise(offset, settings.spaghetti_frequency)),
+            ravine: noise(RAVINE_SEED_OFFSET, settings.ravine_frequency),
+            ravine_mask: noise(RAVINE_MASK_SEED_OFFSET, RAVINE_MASK_FREQUENCY),
+        }
+    }
+
+
+    /// Returns true when a cheese or spaghetti cave passes through the position.
+    fn is_cave(&self, world_position: Vec3, settings: &CaveSettings) -> bool {
+        let Vec3 { x, y, z } = world_position;
+        let y = y * CAVE_VERTICAL_STRETCH;
+
+        // Cheese caves are the large open pockets where the noise peaks
+        if self.cheese.get_noise_3d(x, y, z) > settings.cheese_threshold {
+            return true;
+        }
+
+        // Spaghetti caves follow the lines where two noise fields both cross zero
+        self.spaghetti
+            .iter()
+            .all(|noise| noise.get_noise_3d(x, y, z).abs() < settings.spaghetti_width)
+    }
+
+
+    /// Returns how deep below the surface a ravine reaches at a column, zero where there is none.
+    fn ravine_depth(&self, world_x: f32, world_z: f32, settings: &CaveSettings) -> i32 {
+        if !settings.ravines || self.ravine_mask.get_noise_2d(world_x, world_z) < RAVINE_MASK_THRESHOLD {
+            return 0;
+        }
+
+        // Ravines are deepest along the noise zero line and narrow towards their floor
+        let distance = self.ravine.get_noise_2d(world_x, world_z).abs();
+        if distance >= settings.ravine_width {
+            return 0;
+        }
+
+        ((1.0 - distance / settings.ravine_width) * settings.ravine_depth as f32) as i32
+    }
+}
+
+
+impl CaveCarver {
+    /// Seeds the carver noise from the world seed.
+    pub fn new(seed: u32, settings: &CaveSettings) -> Self {
+        Self {
+            noise: CaveNoise::new(seed, settings),
+            settings: *settings,
+        }
+    }
+
+
+    /// Returns the height a ravine cuts a column down to, the surface height where there is no ravine.
+    fn ravine_floor(&self, world_x: i32, world_z: i32, surface_height: i32) -> i32 {
+        surface_height - self.noise.ravine_depth(world_x as f32, world_z as f32, &self.settings)
+    }
+
+
+    /// Returns true when carving removes the block at a position of a column with the given surface and ravine floor.
+    fn carves(&self, world_position: IVec3, surface_height: i32, ravine_floor: i32) -> bool {
+        // Ravines open up to the sky, caves stay below the minimum depth
+        let in_ravine = world_position.y > ravine_floor;
+        let in_cave = world_position.y <= surface_height - self.settings.min_depth
+            && self.noise.is_cave(Vec3::new(world_position.x as f32, world_position.y as f32, world_position.z as f32), &self.settings);
+
+        in_ravine || in_cave
+    }
+
+
+    /// Returns true when carving removes the surface block of a column.
+    pub fn carves_surface(&self, world_x: i32, world_z: i32, surface_height: i32) -> bool {
+        let ravine_floor = self.ravine_floor(world_x, world_z, surface_height);
+        self.carves(IVec3::new(world_x, surface_height, world_z), surface_height, ravine_floor)
+    }
+}
+
+
+/// Carves caves and ravines out of a chunk whose columns have the given surface heights.
+pub fn carve_caves(
+    chunk: &mut Chunk,
+    chunk_position: &IVec3,
+    cave_carver: &CaveCarver,
+    surface_heights: &SurfaceHeights,
+) {
+    let base_x = chunk_position.x * CHUNK_SIZE_X as i32;
+    let base_y = chunk_position.y * CHUNK_SIZE_Y as i32;
+    let base_z = chunk_position.z * CHUNK_SIZE_Z as i32;
+
+    for (block_z, row) in surface_heights.iter().enumerate() {
+        for (block_x, &surface_height) in row.iter().enumerate() {
+            let world_x = base_x + block_x as i32;
+            let world_z = base_z + block_z as i32;
+            let ravine_floor = cave_carver.ravine_floor(world_x, world_z, surface_height);
+
+            for block_y in 0..CHUNK_SIZE_Y {
+                let world_y = base_y + block_y as i32;
+                if world_y > surface_height {
+                    break;
+                }
+
+                if cave_carver.carves(IVec3::new(world_x, world_y, world_z), surface_height, ravine_floor) {
+                    chunk.set(block_x, block_y, block_z, Block::AIR);
+                }
+            }
+        }
+    }
+}
@@ -1,12 +1,19 @@
 use bevy::asset::RenderAssetUsages;
 use bevy::mesh::PrimitiveTopology;
-use fastnoise_lite::FastNoiseLite;
 use bevy::mesh::Indices;
 use std::sync::LazyLock;
 use bevy::mesh::Mesh;
 use bevy::prelude::*;
 use glam::IVec3;
 
+use crate::registry::*;
+use crate::lighting::*;
+use crate::biome::*;
+use crate::structure::*;
+use crate::terrain::*;
+use crate::cave::*;
+use crate::fluid::*;
+use crate::palette::*;
 use crate::block::*;
 use crate::world::*;
 
@@ -18,6 +25,12 @@ pub const CHUNK_SIZE_XY: usize =
     CHUNK_SIZE_X * CHUNK_SIZE_Z;
 pub const CHUNK_VOLUME: usize = 
     CHUNK_SIZE_X * CHUNK_SIZE_Y * CHUNK_SIZE_Z;
+
+// Surface height of every column in a chunk, indexed by z then x
+pub type SurfaceHeights = [[i32; CHUNK_SIZE_X]; CHUNK_SIZE_Z];
+
+// Percent chance of coal replacing stone
+const COAL_CHANCE: u32 = 5;
     
 
 pub static GET_COORDS: LazyLock<[(usize, usize, usize); CHUNK_VOLUME]> = LazyLock::new(|| {
@@ -37,16 +50,28 @@ pub static GET_COORDS: LazyLock<[(usize, usize, usize); CHUNK_VOLUME]> = LazyLoc
 });
 
 
-#[derive(Clone, Copy)]
+#[derive(Clone)]
 pub struct Chunk {
-    pub blocks: [Block; CHUNK_VOLUME],
+    blocks: PaletteStorage,
+    light: LightStorage,
+}
+
+
+// Blocks terrain generation builds from, resolved by name once when a generator is created
+#[derive(Clone, Copy)]
+pub struct TerrainBlocks {
+    pub stone: Block,
+    pub coal: Block,
+    pub biome_layers: [(Block, Block); Biome::ALL.len()],
+    pub structures: StructureBlocks,
 }
 
 
 impl Chunk {
     pub fn new() -> Self {
         Self {
-            blocks: [Block::default(); CHUNK_VOLUME],
+            blocks: PaletteStorage::filled(Block::default()),
+            light: LightStorage::filled(0),
         }
     }
 
@@ -54,115 +79,887 @@ impl Chunk {
     pub fn get_index(block_x: usize, block_y: usize, block_z: usize) -> usize {
         block_x + block_z * CHUNK_SIZE_X + block_y * CHUNK_SIZE_XY
     }
+
+
+    /// Returns the block at the given local position.
+    pub fn get(&self, block_x: usize, block_y: usize, block_z: usize) -> Block {
+        self.blocks.get(Chunk::get_index(block_x, block_y, block_z))
+    }
+
+
+    /// Sets the block at the given local position.
+    pub fn set(&mut self, block_x: usize, block_y: usize, block_z: usize, block: Block) {
+        self.blocks.set(Chunk::get_index(block_x, block_y, block_z), block);
+    }
+
+
+    /// Returns the block at the given block index.
+    pub fn get_at(&self, block_index: usize) -> Block {
+        self.blocks.get(block_index)
+    }
+
+
+    /// Returns true when every block in the chunk is air.
+    pub fn is_empty(&self) -> bool {
+        self.blocks.uniform() == Some(Block::AIR)
+    }
+
+
+    /// Creates an unlit chunk from previously stored blocks.
+    pub fn from_storage(blocks: PaletteStorage) -> Self {
+        Self {
+            blocks,
+            light: LightStorage::filled(0),
+        }
+    }
+
+
+    /// Returns the light level of a channel at the given block index.
+    pub fn get_light(&self, block_index: usize, channel: LightChannel) -> u8 {
+        self.light.get(block_index, channel)
+    }
+
+
+    /// Sets the light level of a channel at the given block index.
+    pub fn set_light(&mut self, block_index: usize, channel: LightChannel, level: u8) {
+        self.light.set(block_index, channel, level);
+    }
+
+
+    /// Sets a light channel to the same level across the whole chunk.
+    pub fn fill_light(&mut self, channel: LightChannel, level: u8) {
+        self.light.fill(channel, level);
+    }
+
+
+    /// Shrinks light storage once lighting is done when the chunk is evenly lit.
+    pub fn compact_light(&mut self) {
+        self.light.compact();
+    }
+
+
+    /// Returns the palette storage holding the chunks blocks.
+    pub fn storage(&self) -> &PaletteStorage {
+        &self.blocks
+    }
+
+
+    /// Drops unused palette entries after bulk edits such as generation.
+    pub fn compact(&mut self) {
+        self.blocks.compact();
+    }
+}
+
+
+/// Hashes a world position with the seed into a well mixed pseudo random value.
+pub fn hash_position(seed: u32, world_position: IVec3) -> u32 {
+    let mut hash = seed as u64 ^ 0x9E37_79B9_7F4A_7C15;
+
+    for value in [world_position.x, world_position.y, world_position.z] {
+        hash ^= value as u32 as u64;
+        hash = hash.wrapping_mul(0xFF51_AFD7_ED55_8CCD);
+        hash ^= hash >> 33;
+    }
+
+    hash = hash.wrapping_mul(0xC4CE_B9FE_1A85_EC53);
+    (hash ^ (hash >> 33)) as u32
+}
+
+
+impl TerrainBlocks {
+    /// Looks up the ore, biome surface and subsurface and feature blocks, failing when one is missing.
+    pub fn resolve(registry: &BlockRegistry) -> Result<Self, String> {
+        let mut biome_layers = [(Block::AIR, Block::AIR); Biome::ALL.len()];
+        for biome in Biome::ALL {
+            let profile = biome.profile();
+            biome_layers[biome as usize] = (registry.require(profile.surface)?, registry.require(profile.subsurface)?);
+        }
+
+        Ok(Self {
+            stone: registry.require("stone")?,
+            coal: registry.require("coal_ore")?,
+            biome_layers,
+            structures: StructureBlocks::resolve(registry)?,
+        })
+    }
+
+
+    /// Returns the block covering the top of a biomes columns.
+    pub fn surface(&self, biome: Biome) -> Block {
+        self.biome_layers[biome as usize].0
+    }
+}
+
+
+/// Generates biome shaped noise terrain with caves and structures.
+pub fn prepare_geometry(
+    chunk_position: &IVec3, 
+    seed: u32, 
+    terrain_blocks: &TerrainBlocks, 
+    terrain_noise: &TerrainNoise,
+    cave_settings: &CaveSettings,
+) -> Chunk {
+    let column_at = |world_x: i32, world_z: i32| terrain_noise.column_at(world_x, world_z);
+    let (mut chunk, surface_heights) = fill_columns(chunk_position, seed, terrain_blocks, &column_at);
+
+    let cave_carver = CaveCarver::new(seed, cave_settings);
+    carve_caves(&mut chunk, chunk_position, &cave_carver, &surface_heights);
+    place_structures(&mut chunk, chunk_position, seed, &terrain_blocks.structures, column_at, Some(&cave_carver));
+
+    chunk.compact();
+    chunk
 }
 
 
-pub fn prepare_geometry(chunk_position: &IVec3) -> Chunk {
+/// Fills every column up to its surface with the layers of its biome over ore speckled stone.
+pub fn fill_columns(
+    chunk_position: &IVec3, 
+    seed: u32, 
+    terrain_blocks: &TerrainBlocks, 
+    column_at: &impl Fn(i32, i32) -> BiomeColumn,
+) -> (Chunk, SurfaceHeights) {
     let mut chunk = Chunk::new();
-    let mut noise = FastNoiseLite::new();
-    noise.set_noise_type(Some(fastnoise_lite::NoiseType::Perlin));
-    noise.set_seed(Some(213123));
-    noise.set_frequency(Some(0.065));
-    
+    let mut surface_heights = [[0; CHUNK_SIZE_X]; CHUNK_SIZE_Z];
+    let TerrainBlocks { stone, coal, .. } = *terrain_blocks;
 
     let base_x = chunk_position.x as f32 * CHUNK_SIZE_X as f32;
+    let base_y = chunk_position.y * CHUNK_SIZE_Y as i32;
     let base_z = chunk_position.z as f32 * CHUNK_SIZE_Z as f32;
 
-    for block_z in 0..CHUNK_SIZE_Z {
-        for block_x in 0..CHUNK_SIZE_X {
+    for (block_z, row) in surface_heights.iter_mut().enumerate() {
+        for (block_x, surface_height) in row.iter_mut().enumerate() {
+            let world_x = base_x + block_x as f32;
+            let world_z = base_z + block_z as f32;
+            let column = column_at(world_x as i32, world_z as i32);
+            let height = column.height;
+            let (surface, subsurface) = terrain_blocks.biome_layers[column.biome as usize];
+            let subsurface_depth = column.biome.profile().subsurface_depth;
+            *surface_height = height;
+
             for block_y in 0..CHUNK_SIZE_Y {
-                let world_x = base_x + block_x as f32;
-                let world_z = base_z + block_z as f32;
-                let height = noise.get_noise_2d(world_x, world_z) * 5.0 + 8.0;
+                let world_y = base_y + block_y as i32;
 
-                if block_y > height as usize {
-                    continue;
+                if world_y > height {
+                    break;
                 }
 
-                let block_type = if block_y == height as usize {  
-                    BlockType::Grass
+                let block = if world_y == height {  
+                    surface
                 }
-                else if block_y >= (height as usize).saturating_sub(3) {
-                    BlockType::Dirt
+                else if world_y >= height - subsurface_depth {
+                    subsurface
                 }
                 else {
-                    if BlockType::get_chance(BlockType::Coal) {
-                        BlockType::Coal
+                    let world_position = IVec3::new(world_x as i32, world_y, world_z as i32);
+                    if hash_position(seed, world_position) % 100 < COAL_CHANCE {
+                        coal
                     } 
                     else {
-                        BlockType::Stone
+                        stone
                     }
                 };
 
-                chunk.blocks[Chunk::get_index(block_x, block_y, block_z)] = Block { block_type };
+                chunk.set(block_x, block_y, block_z, block);
             }
         }
     }
-    
-    chunk
+
+    (chunk, surface_heights)
+}
+
+
+// Selects the algorithm used to turn chunk blocks into mesh faces
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum MeshingMode {
+    Naive,
+    Greedy,
+}
+
+
+// Meshing mode, greedy unless the VOXEL_MESHER environment variable asks for naive
+pub static MESHING_MODE: LazyLock<MeshingMode> = LazyLock::new(|| {
+    match std::env::var("VOXEL_MESHER").as_deref() {
+        Ok("naive") => MeshingMode::Naive,
+        _ => MeshingMode::Greedy,
+    }
+});
+
+
+// Normal axis for each face in face index order
+const FACE_AXES: [usize; 6] = [2, 2, 0, 0, 1, 1];
+
+
+// Vertex brightness for each ambient occlusion level, from fully occluded to open
+const OCCLUSION_BRIGHTNESS: [f32; 4] = [0.4, 0.6, 0.8, 1.0];
+
+
+// Brightness lost per light level below full light and the floor it fades to
+const LIGHT_FALLOFF: f32 = 0.8;
+const MIN_LIGHT_BRIGHTNESS: f32 = 0.03;
+
+
+// Accumulates vertex data for a chunk mesh
+#[derive(Default)]
+struct MeshBuilder {
+    vertices: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    uvs: Vec<[f32; 2]>,
+    tiles: Vec<[f32; 2]>,
+    colors: Vec<[f32; 4]>,
+    indices: Vec<u32>,
+}
+
+
+// Separate builders for faces drawn in the opaque and the alpha blended pass
+#[derive(Default)]
+struct ChunkMeshBuilders {
+    opaque: MeshBuilder,
+    translucent: MeshBuilder,
+}
+
+
+// Meshes of a chunk split by render pass, translucent faces are only present when the chunk has any
+pub struct ChunkMeshes {
+    pub opaque: Mesh,
+    pub translucent: Option<(Mesh, TranslucentFaces)>,
+}
+
+
+// Centre and triangle indices of each translucent quad, used to reorder them back to front
+#[derive(Component, Clone, Default)]
+pub struct TranslucentFaces {
+    pub centers: Vec<Vec3>,
+    pub indices: Vec<[u32; 6]>,
+}
+
+
+// Level of every light channel at a face corner, in LightChannel::ALL order
+type CornerLight = [u8; 4];
+
+
+// Everything two faces must share for the greedy mesher to merge them
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct FaceKey {
+    block: Block,
+    occlusion: [u8; 4],
+    light: [CornerLight; 4],
+}
+
+
+impl FaceKey {
+    /// Returns true when the face has no occlusion or light gradient and can be stretched over several blocks.
+    fn is_mergeable(&self) -> bool {
+        self.occlusion.iter().all(|level| *level == self.occlusion[0])
+            && self.light.iter().all(|level| *level == self.light[0])
+    }
 }
 
 
-pub fn build_mesh(chunk_position: IVec3, world: &WorldChunks) -> Mesh {
+impl MeshBuilder {
+    /// Adds a block face stretched over the given size in blocks.
+    fn push_face(
+        &mut self, 
+        face_index: usize, 
+        block_offset: [f32; 3], 
+        face_size: [f32; 3], 
+        tile: [f32; 2],
+        occlusion: [u8; 4],
+        light: [CornerLight; 4],
+    ) {
+        let index_counter = self.vertices.len() as u32;
+        let first_vertex = self.vertices.len();
+
+        offset_vertices(
+            &VERTICES[face_index], 
+            block_offset, 
+            face_size,
+            &mut self.vertices, 
+        );
+
+        // Uvs count blocks along each edge so the shader can repeat the atlas tile
+        let face_vertices = &self.vertices[first_vertex..];
+        let uv_width = edge_length(face_vertices[0], face_vertices[1]);
+        let uv_height = edge_length(face_vertices[1], face_vertices[2]);
+        for uv in FACE_UVS {
+            self.uvs.push([uv[0] * uv_width, uv[1] * uv_height]);
+        }
+
+        self.normals.extend_from_slice(&NORMALS[face_index]);
+        self.tiles.extend_from_slice(&[tile; 4]);
+        // Each color channel is lit by white skylight or its block light channel, whichever is brighter
+        let colors: [[f32; 4]; 4] = std::array::from_fn(|corner| {
+            let occlusion_brightness = OCCLUSION_BRIGHTNESS[occlusion[corner] as usize];
+            let [sky, red, green, blue] = light[corner];
+            [
+                occlusion_brightness * light_brightness(sky.max(red)),
+                occlusion_brightness * light_brightness(sky.max(green)),
+                occlusion_brightness * light_brightness(sky.max(blue)),
+                1.0,
+            ]
+        });
+        self.colors.extend_from_slice(&colors);
+
+        // Split along the brighter diagonal so shading interpolates evenly across the quad
+        let brightness = colors.map(|color| color[0] + color[1] + color[2]);
+        let indices = if brightness[0] + brightness[2] < brightness[1] + brightness[3] { FLIPPED_INDICES } else { INDICES };
+        for index in indices {
+            self.indices.push(index_counter + index);
+        }
+    }
+
+
+    /// Returns the centre and indices of every quad so translucent faces can be sorted.
+    fn faces(&self) -> TranslucentFaces {
+        let centers = self.vertices
+            .chunks_exact(4)
+            .map(|corners| corners.iter().map(|corner| Vec3::from_array(*corner)).sum::<Vec3>() / 4.0)
+            .collect();
+        let indices = self.indices
+            .chunks_exact(6)
+            .map(|quad| quad.try_into().unwrap())
+            .collect();
+
+        TranslucentFaces { centers, indices }
+    }
+
+
+    /// Converts the collected vertex data into a mesh.
+    fn build(self) -> Mesh {
+        let mut chunk_mesh = Mesh::new(
+            PrimitiveTopology::TriangleList,
+            RenderAssetUsages::default(),
+        );
+
+        chunk_mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, self.vertices);
+        chunk_mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals);
+        chunk_mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, self.uvs);
+        chunk_mesh.insert_attribute(Mesh::ATTRIBUTE_UV_1, self.tiles);
+        chunk_mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, self.colors);
+        chunk_mesh.insert_indices(Indices::U32(self.indices));
+
+        chunk_mesh
+    }
+}
+
+
+impl ChunkMeshBuilders {
+    /// Returns the builder for the render pass a block is drawn in.
+    fn for_block(&mut self, registry: &BlockRegistry, block: Block) -> &mut MeshBuilder {
+        if registry.is_translucent(block) { &mut self.translucent } else { &mut self.opaque }
+    }
+
+
+    /// Converts both builders into chunk meshes.
+    fn build(self) -> ChunkMeshes {
+        let translucent = (!self.translucent.vertices.is_empty()).then(|| {
+            let faces = self.translucent.faces();
+            (self.translucent.build(), faces)
+        });
+
+        ChunkMeshes {
+            opaque: self.opaque.build(),
+            translucent,
+        }
+    }
+}
+
+
+/// Builds the chunk meshes using the configured meshing mode.
+pub fn build_mesh(chunk_position: IVec3, world: &WorldChunks, registry: &BlockRegistry) -> ChunkMeshes {
+    build_mesh_with(*MESHING_MODE, chunk_position, world, registry)
+}
+
+
+/// Builds the chunk meshes using the given meshing mode.
+pub fn build_mesh_with(
+    meshing_mode: MeshingMode, 
+    chunk_position: IVec3, 
+    world: &WorldChunks, 
+    registry: &BlockRegistry,
+) -> ChunkMeshes {
+    match meshing_mode {
+        MeshingMode::Naive => build_naive_mesh(chunk_position, world, registry),
+        MeshingMode::Greedy => build_greedy_mesh(chunk_position, world, registry),
+    }
+}
+
+
+/// Builds a fully lit unit cube of a single block centred on the origin.
+pub fn build_block_mesh(registry: &BlockRegistry, block: Block) -> Mesh {
+    let mut mesh_builder = MeshBuilder::default();
+    let face_textures = registry.get(block).face_textures;
+
+    for (face_index, texture) in face_textures.into_iter().enumerate() {
+        mesh_builder.push_face(
+            face_index, 
+            [-0.5; 3], 
+            [1.0; 3], 
+            texture, 
+            [3; 4], 
+            [[MAX_LIGHT, 0, 0, 0]; 4],
+        );
+    }
+
+    mesh_builder.build()
+}
+
+
+/// Emits one quad for every visible block face.
+fn build_naive_mesh(chunk_position: IVec3, world: &WorldChunks, registry: &BlockRegistry) -> ChunkMeshes {
     let chunk = &world.chunks[&chunk_position];
-    let mut chunk_vertices: Vec<[f32; 3]> = Vec::new();
-    let mut chunk_normals: Vec<[f32; 3]> = Vec::new();
-    let mut chunk_uvs: Vec<[f32; 2]> = Vec::new();
-    let mut chunk_indices: Vec<u32> = Vec::new();
-    let mut index_counter = 0;
+    let mut mesh_builders = ChunkMeshBuilders::default();
 
     for block_index in 0..CHUNK_VOLUME {
-        if !chunk.blocks[block_index].is_solid() {
+        let block = chunk.get_at(block_index);
+        if block == Block::AIR {
             continue;
         }
 
         let (block_x, block_y, block_z) = GET_COORDS[block_index];
-        let visible_faces = get_visibility((block_x, block_y, block_z), &chunk, &world, chunk_position);
+        let visible_faces = get_visibility((block_x, block_y, block_z), chunk, world, chunk_position, registry);
 
         if visible_faces.is_empty() {
             continue;
         }
 
-        let block = chunk.blocks[block_index];
-        let block_offset = [
-            block_x as f32, 
-            block_y as f32, 
-            block_z as f32,
-        ];
-
         for face in visible_faces {
             let face_index = face.bits().trailing_zeros() as usize;
-            let face_vertices = VERTICES[face_index];
-            let face_normals = NORMALS[face_index];
-            let face_uvs = block.block_type.get_texture();
+            let texture = registry.get(block).face_textures[face_index];
+            let (occlusion, light) = get_face_shading([block_x, block_y, block_z], face_index, chunk, world, chunk_position, registry);
+            let (face_bottom, face_top) = get_face_span([block_x, block_y, block_z], face_index, block, chunk, world, chunk_position, registry);
+            let block_offset = [
+                block_x as f32, 
+                block_y as f32 + face_bottom, 
+                block_z as f32,
+            ];
+            mesh_builders
+                .for_block(registry, block)
+                .push_face(face_index, block_offset, [1.0, face_top - face_bottom, 1.0], texture, occlusion, light);
+        }
+    }
+
+    mesh_builders.build()
+}
+
+
+/// Merges coplanar visible faces of the same block type and shading into larger quads.
+fn build_greedy_mesh(chunk_position: IVec3, world: &WorldChunks, registry: &BlockRegistry) -> ChunkMeshes {
+    let chunk = &world.chunks[&chunk_position];
+    let chunk_size = [CHUNK_SIZE_X, CHUNK_SIZE_Y, CHUNK_SIZE_Z];
+    let mut mesh_builders = ChunkMeshBuilders::default();
+
+    for (face_index, normal_axis) in FACE_AXES.into_iter().enumerate() {
+        let u_axis = (normal_axis + 1) % 3;
+        let v_axis = (normal_axis + 2) % 3;
+        let (size_u, size_v) = (chunk_size[u_axis], chunk_size[v_axis]);
+        let mut mask: Vec<Option<FaceKey>> = vec![None; size_u * size_v];
+
+        for layer in 0..chunk_size[normal_axis] {
+            // Mark every visible face within this layer
+            for v in 0..size_v {
+                for u in 0..size_u {
+                    let mut block_position = [0; 3];
+                    block_position[normal_axis] = layer;
+                    block_position[u_axis] = u;
+                    block_position[v_axis] = v;
+
+                    let block = chunk.get(block_position[0], block_position[1], block_position[2]);
+                    let visible = block != Block::AIR 
+                        && is_face_visible(block_position, face_index, chunk, world, chunk_position, registry);
+
+                    mask[u + v * size_u] = visible.then(|| {
+                        let (occlusion, light) = get_face_shading(block_position, face_index, chunk, world, chunk_position, registry);
+                        FaceKey { block, occlusion, light }
+                    });
+                }
+            }
+
+            // Grow each unmerged face along u then v and emit one quad per rectangle
+            for v in 0..size_v {
+                let mut u = 0;
+                while u < size_u {
+                    let Some(face_key) = mask[u + v * size_u] else {
+                        u += 1;
+                        continue;
+                    };
+
+                    // Faces with a shading gradient stay a single block so their corners stay correct
+                    let mergeable = face_key.is_mergeable() && !registry.is_fluid(face_key.block);
+
+                    let mut width = 1;
+                    while mergeable && u + width < size_u && mask[u + width + v * size_u] == Some(face_key) {
+                        width += 1;
+                    }
 
-            offset_vertices(
-                &face_vertices, 
-                block_offset, 
-                &mut chunk_vertices, 
-            );
+                    let mut height = 1;
+                    'grow: while mergeable && v + height < size_v {
+                        for offset in 0..width {
+                            if mask[u + offset + (v + height) * size_u] != Some(face_key) {
+                                break 'grow;
+                            }
+                        }
+                        height += 1;
+                    }
+
+                    for offset_v in 0..height {
+                        for offset_u in 0..width {
+                            mask[u + offset_u + (v + offset_v) * size_u] = None;
+                        }
+                    }
 
-            chunk_normals.extend_from_slice(&face_normals);
-            chunk_uvs.extend_from_slice(&face_uvs);
+                    let mut block_offset = [0.0; 3];
+                    block_offset[normal_axis] = layer as f32;
+                    block_offset[u_axis] = u as f32;
+                    block_offset[v_axis] = v as f32;
+
+                    let mut face_size = [1.0; 3];
+                    face_size[u_axis] = width as f32;
+                    face_size[v_axis] = height as f32;
+
+                    // Fluids are never merged so their surface can sit lower than a full block
+                    if registry.is_fluid(face_key.block) {
+                        let mut block_position = [0; 3];
+                        block_position[normal_axis] = layer;
+                        block_position[u_axis] = u;
+                        block_position[v_axis] = v;
+                        let (face_bottom, face_top) = get_face_span(block_position, face_index, face_key.block, chunk, world, chunk_position, registry);
+                        block_offset[1] += face_bottom;
+                        face_size[1] = face_top - face_bottom;
+                    }
 
-            for index in 0..6 {
-                chunk_indices.push(index_counter + INDICES[index]);
+                    let texture = registry.get(face_key.block).face_textures[face_index];
+                    mesh_builders
+                        .for_block(registry, face_key.block)
+                        .push_face(face_index, block_offset, face_size, texture, face_key.occlusion, face_key.light);
+                    u += width;
+                }
             }
-            index_counter += 4;
         }
     }
 
-    let mut chunk_mesh = Mesh::new(
-        PrimitiveTopology::TriangleList,
-        RenderAssetUsages::default(),
-    );
-    
-    chunk_mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, chunk_vertices);
-    chunk_mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, chunk_normals);
-    chunk_mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, chunk_uvs);
-    chunk_mesh.insert_indices(Indices::U32(chunk_indices));
+    mesh_builders.build()
+}
+
+
+/// Builds the chunk meshes at a level of detail, zero being full resolution and each level halving it.
+pub fn build_lod_mesh(chunk_position: IVec3, world: &WorldChunks, registry: &BlockRegistry, lod: u8) -> ChunkMeshes {
+    if lod == 0 {
+        return build_mesh(chunk_position, world, registry);
+    }
+
+    build_downsampled_mesh(chunk_position, world, registry, 1 << lod)
+}
+
+
+/// Emits a quad for every visible face of cells several blocks wide, each drawn as the topmost block inside it.
+fn build_downsampled_mesh(chunk_position: IVec3, world: &WorldChunks, registry: &BlockRegistry, cell_size: i32) -> ChunkMeshes {
+    let chunk_origin = chunk_position * IVec3::new(CHUNK_SIZE_X as i32, CHUNK_SIZE_Y as i32, CHUNK_SIZE_Z as i32);
+    let mut mesh_builders = ChunkMeshBuilders::default();
+
+    for cell_y in (0..CHUNK_SIZE_Y as i32).step_by(cell_size as usize) {
+        for cell_z in (0..CHUNK_SIZE_Z as i32).step_by(cell_size as usize) {
+            for cell_x in (0..CHUNK_SIZE_X as i32).step_by(cell_size as usize) {
+                let cell_offset = IVec3::new(cell_x, cell_y, cell_z);
+                let Some(block) = get_cell_block(world, chunk_origin + cell_offset, cell_size) else { continue; };
+
+                for (face_index, direction) in CHUNK_NEIGHBOURS.into_iter().enumerate() {
+                    let neighbor_origin = chunk_origin + cell_offset + direction * cell_size;
+                    let crosses_border = get_block_location(neighbor_origin).0 != chunk_position;
+
+                    // Across a chunk border the neighbour may be meshed finer and only draw its real blocks,
+                    // so the face is only hidden when the real blocks touching it cover it completely
+                    let visible = if crosses_border {
+                        !is_cell_side_covered(world, registry, neighbor_origin, face_index, cell_size)
+                    }
+                    else {
+                        match get_cell_block(world, neighbor_origin, cell_size) {
+                            Some(neighbor) => !registry.is_opaque(neighbor) && neighbor.id != block.id,
+                            None => true,
+                        }
+                    };
+                    if !visible {
+                        continue;
+                    }
+
+                    let light = get_cell_side_light(world, registry, neighbor_origin, face_index, cell_size);
+
+                    mesh_builders.for_block(registry, block).push_face(
+                        face_index,
+                        [cell_x as f32, cell_y as f32, cell_z as f32],
+                        [cell_size as f32; 3],
+                        registry.get(block).face_textures[face_index],
+                        [3; 4],
+                        [light; 4],
+                    );
+                }
+            }
+        }
+    }
+
+    mesh_builders.build()
+}
+
+
+/// Returns the positions of the blocks of a neighbouring cell that touch the given face.
+fn get_cell_side(neighbor_origin: IVec3, face_index: usize, cell_size: i32) -> impl Iterator<Item = IVec3> {
+    let normal_axis = FACE_AXES[face_index];
+    let u_axis = (normal_axis + 1) % 3;
+    let v_axis = (normal_axis + 2) % 3;
+
+    // Faces pointing along the negative axis touch the far layer of the neighbouring cell
+    let mut layer_origin = neighbor_origin;
+    if CHUNK_NEIGHBOURS[face_index][normal_axis] < 0 {
+        layer_origin[normal_axis] += cell_size - 1;
+    }
+
+    (0..cell_size).flat_map(move |v| {
+        (0..cell_size).map(move |u| {
+            let mut block_position = layer_origin;
+            block_position[u_axis] += u;
+            block_position[v_axis] += v;
+            block_position
+        })
+    })
+}
+
+
+/// Returns true when every block of a neighbouring cell on the side facing the given face is loaded and opaque.
+fn is_cell_side_covered(world: &WorldChunks, registry: &BlockRegistry, neighbor_origin: IVec3, face_index: usize, cell_size: i32) -> bool {
+    get_cell_side(neighbor_origin, face_index, cell_size)
+        .all(|block_position| world.get_block(block_position).is_some_and(|block| registry.is_opaque(block)))
+}
+
+
+/// Returns the brightest light of the non opaque blocks touching a cell face, like the full resolution mesher
+/// sampling the block in front of a face, with unloaded or fully covered sides lit as open sky.
+fn get_cell_side_light(world: &WorldChunks, registry: &BlockRegistry, neighbor_origin: IVec3, face_index: usize, cell_size: i32) -> CornerLight {
+    let mut light: Option<CornerLight> = None;
+    for block_position in get_cell_side(neighbor_origin, face_index, cell_size) {
+        if world.get_block(block_position).is_none_or(|block| registry.is_opaque(block)) {
+            continue;
+        }
+
+        let block_light = LightChannel::ALL.map(|channel| world.get_light(block_position, channel).unwrap_or(0));
+        light = Some(match light {
+            Some(brightest) => std::array::from_fn(|channel| block_light[channel].max(brightest[channel])),
+            None => block_light,
+        });
+    }
+
+    light.unwrap_or(LightChannel::ALL.map(|channel| if channel == LightChannel::Sky { MAX_LIGHT } else { 0 }))
+}
+
+
+/// Returns the topmost block of a cell, so a cell holding any block is drawn filled and never leaves a gap.
+fn get_cell_block(world: &WorldChunks, cell_origin: IVec3, cell_size: i32) -> Option<Block> {
+    // Cells never straddle chunks since their size divides the chunk size
+    let (chunk_position, (local_x, local_y, local_z)) = get_block_location(cell_origin);
+    let chunk = world.chunks.get(&chunk_position)?;
+    if chunk.is_empty() {
+        return None;
+    }
+
+    for offset_y in (0..cell_size as usize).rev() {
+        for offset_z in 0..cell_size as usize {
+            for offset_x in 0..cell_size as usize {
+                let block = chunk.get(local_x + offset_x, local_y + offset_y, local_z + offset_z);
+                if block != Block::AIR {
+                    return Some(block);
+                }
+            }
+        }
+    }
+
+    None
+}
+
+
+/// Returns the length of an axis aligned quad edge.
+fn edge_length(start: [f32; 3], end: [f32; 3]) -> f32 {
+    (end[0] - start[0]).abs() + (end[1] - start[1]).abs() + (end[2] - start[2]).abs()
+}
+
+
+/// Returns the chunk and block index of a position relative to the chunk, looking into neighbouring chunks when outside it.
+fn get_relative_voxel<'a>(
+    block_position: IVec3,
+    chunk: &'a Chunk,
+    world: &'a WorldChunks,
+    chunk_position: IVec3,
+) -> Option<(&'a Chunk, usize)> {
+    let chunk_size = IVec3::new(CHUNK_SIZE_X as i32, CHUNK_SIZE_Y as i32, CHUNK_SIZE_Z as i32);
+    let chunk_offset = block_position.div_euclid(chunk_size);
+    let local = block_position.rem_euclid(chunk_size);
+    let block_index = Chunk::get_index(local.x as usize, local.y as usize, local.z as usize);
+
+    if chunk_offset == IVec3::ZERO {
+        return Some((chunk, block_index));
+    }
+
+    world.chunks
+        .get(&(chunk_position + chunk_offset))
+        .map(|neighbor| (neighbor, block_index))
+}
+
+
+/// Returns the block at a position relative to the chunk, looking into neighbouring chunks when outside it.
+fn get_relative_block(
+    block_position: IVec3,
+    chunk: &Chunk,
+    world: &WorldChunks,
+    chunk_position: IVec3,
+) -> Option<Block> {
+    get_relative_voxel(block_position, chunk, world, chunk_position)
+        .map(|(chunk, block_index)| chunk.get_at(block_index))
+}
+
+
+/// Returns true when the given face of a block is not covered by an opaque neighbour or the same see through block.
+fn is_face_visible(
+    block_position: [usize; 3],
+    face_index: usize,
+    chunk: &Chunk,
+    world: &WorldChunks,
+    chunk_position: IVec3,
+    registry: &BlockRegistry,
+) -> bool {
+    let neighbor_position = IVec3::new(
+        block_position[0] as i32, 
+        block_position[1] as i32, 
+        block_position[2] as i32,
+    ) + CHUNK_NEIGHBOURS[face_index];
+    let block = chunk.get(block_position[0], block_position[1], block_position[2]);
+
+    // Faces between two blocks of the same see through kind, such as glass on glass or water on water, are hidden,
+    // except the sides of a fluid rising above the surface of the same fluid next to it
+    match get_relative_block(neighbor_position, chunk, world, chunk_position) {
+        Some(neighbor) if neighbor.id == block.id && registry.is_fluid(block) && FACE_AXES[face_index] != 1 => {
+            let block_position = IVec3::new(block_position[0] as i32, block_position[1] as i32, block_position[2] as i32);
+            get_fluid_height(neighbor_position, neighbor, chunk, world, chunk_position) 
+                < get_fluid_height(block_position, block, chunk, world, chunk_position)
+        }
+        Some(neighbor) => !registry.is_opaque(neighbor) && neighbor.id != block.id,
+        None => true,
+    }
+}
+
 
-    chunk_mesh
+/// Returns the bottom and top of a face within its block, fluids sit lower unless more of the same fluid is on top
+/// of them and their sides start at the surface of the same fluid next to them.
+fn get_face_span(
+    block_position: [usize; 3],
+    face_index: usize,
+    block: Block,
+    chunk: &Chunk,
+    world: &WorldChunks,
+    chunk_position: IVec3,
+    registry: &BlockRegistry,
+) -> (f32, f32) {
+    if !registry.is_fluid(block) {
+        return (0.0, 1.0);
+    }
+
+    let block_position = IVec3::new(block_position[0] as i32, block_position[1] as i32, block_position[2] as i32);
+    let top = get_fluid_height(block_position, block, chunk, world, chunk_position);
+    if FACE_AXES[face_index] == 1 {
+        return (0.0, top);
+    }
+
+    let neighbor_position = block_position + CHUNK_NEIGHBOURS[face_index];
+    let bottom = match get_relative_block(neighbor_position, chunk, world, chunk_position) {
+        Some(neighbor) if neighbor.id == block.id => get_fluid_height(neighbor_position, neighbor, chunk, world, chunk_position),
+        _ => 0.0,
+    };
+
+    (bottom.min(top), top)
+}
+
+
+/// Returns the surface height of a fluid block at a position relative to the chunk, full when the same fluid is on top.
+fn get_fluid_height(
+    block_position: IVec3,
+    block: Block,
+    chunk: &Chunk,
+    world: &WorldChunks,
+    chunk_position: IVec3,
+) -> f32 {
+    match get_relative_block(block_position + IVec3::Y, chunk, world, chunk_position) {
+        Some(above) if above.id == block.id => 1.0,
+        _ => fluid_height(block.state),
+    }
+}
+
+
+/// Returns the brightness of a vertex with the given light level.
+fn light_brightness(level: u8) -> f32 {
+    LIGHT_FALLOFF.powi((MAX_LIGHT - level) as i32).max(MIN_LIGHT_BRIGHTNESS)
+}
+
+
+/// Returns the ambient occlusion level from 0 fully occluded to 3 open and the smoothed light of each face corner.
+fn get_face_shading(
+    block_position: [usize; 3],
+    face_index: usize,
+    chunk: &Chunk,
+    world: &WorldChunks,
+    chunk_position: IVec3,
+    registry: &BlockRegistry,
+) -> ([u8; 4], [CornerLight; 4]) {
+    let normal_axis = FACE_AXES[face_index];
+    let facing_position = IVec3::new(
+        block_position[0] as i32, 
+        block_position[1] as i32, 
+        block_position[2] as i32,
+    ) + CHUNK_NEIGHBOURS[face_index];
+
+    let is_occluder = |offset: IVec3| {
+        get_relative_block(facing_position + offset, chunk, world, chunk_position)
+            .is_some_and(|block| registry.is_opaque(block))
+    };
+
+    // Unloaded neighbours count as open sky so chunk borders are not darkened while they load
+    let corner_light = |offset: IVec3| -> CornerLight {
+        match get_relative_voxel(facing_position + offset, chunk, world, chunk_position) {
+            Some((chunk, block_index)) => LightChannel::ALL.map(|channel| chunk.get_light(block_index, channel)),
+            None => [MAX_LIGHT, 0, 0, 0],
+        }
+    };
+
+    let mut occlusion = [3; 4];
+    let mut light = [[0; 4]; 4];
+    for (corner, vertex) in VERTICES[face_index].iter().enumerate() {
+        // Step from the facing block towards the corner along both in-plane axes
+        let mut side_offsets = [IVec3::ZERO; 2];
+        let tangent_axes = (0..3).filter(|axis| *axis != normal_axis);
+        for (side_offset, axis) in side_offsets.iter_mut().zip(tangent_axes) {
+            side_offset[axis] = if vertex[axis] > 0.5 { 1 } else { -1 };
+        }
+
+        let side_a = is_occluder(side_offsets[0]);
+        let side_b = is_occluder(side_offsets[1]);
+        let diagonal = is_occluder(side_offsets[0] + side_offsets[1]);
+
+        occlusion[corner] = if side_a && side_b { 0 } else { 3 - side_a as u8 - side_b as u8 - diagonal as u8 };
+
+        // Average the light of the open blocks around the corner, the diagonal is hidden when both sides are blocked
+        let open_offsets = [
+            (true, IVec3::ZERO),
+            (!side_a, side_offsets[0]),
+            (!side_b, side_offsets[1]),
+            (!(diagonal || (side_a && side_b)), side_offsets[0] + side_offsets[1]),
+        ];
+        let mut light_sum = [0u32; 4];
+        let mut samples = 0;
+        for (_open, offset) in open_offsets.iter().filter(|(open, _offset)| *open) {
+            for (sum, level) in light_sum.iter_mut().zip(corner_light(*offset)) {
+                *sum += level as u32;
+            }
+            samples += 1;
+        }
+        light[corner] = light_sum.map(|sum| (sum / samples) as u8);
+    }
+
+    (occlusion, light)
 }
 
 
@@ -171,92 +968,163 @@ fn get_visibility(
     chunk: &Chunk,
     world: &WorldChunks,
     chunk_position: IVec3,
+    registry: &BlockRegistry,
 ) -> BlockFaces {
     let mut visible_faces = BlockFaces::empty();
 
-    if block_z + 1 >= CHUNK_SIZE_Z {
-        if let Some(neighbor) = world.chunks.get(&(chunk_position + CHUNK_NEIGHBOURS[0])) {
-            if !neighbor.blocks[Chunk::get_index(block_x, block_y, 0)].is_solid() {
-                visible_faces |= BlockFaces::FRONT_FACE;
-            }
-        } 
-        else {
-            visible_faces |= BlockFaces::FRONT_FACE;
+    for face_index in 0..6 {
+        if is_face_visible([block_x, block_y, block_z], face_index, chunk, world, chunk_position, registry) {
+            visible_faces |= BlockFaces::from_bits_retain(1 << face_index);
         }
-    } 
-    else if !chunk.blocks[Chunk::get_index(block_x, block_y, block_z + 1)].is_solid() {
-        visible_faces |= BlockFaces::FRONT_FACE;
     }
 
-    if block_z == 0 {
-        if let Some(neighbor) = world.chunks.get(&(chunk_position + CHUNK_NEIGHBOURS[1])) {
-            if !neighbor.blocks[Chunk::get_index(block_x, block_y, CHUNK_SIZE_Z - 1)].is_solid() {
-                visible_faces |= BlockFaces::BACK_FACE;
-            }
-        } 
-        else {
-            visible_faces |= BlockFaces::BACK_FACE;
+    visible_faces
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use bevy::mesh::VertexAttributeValues;
+
+    use super::*;
+
+
+    const TEST_BLOCKS: &str = r#"[
+        (name: "air", id: 0, solid: false, opacity: Transparent),
+        (name: "stone", id: 1, textures: All((1, 0))),
+        (name: "dirt", id: 2, textures: All((2, 0))),
+        (name: "grass", id: 3, textures: Sides(top: (0, 0), bottom: (2, 0), sides: (3, 0))),
+    ]"#;
+
+
+    // Positions, uvs and atlas tile of one quad
+    struct Quad {
+        corners: [[f32; 3]; 4],
+        uvs: [[f32; 2]; 4],
+        tile: [f32; 2],
+    }
+
+
+    /// Builds a registry from the test block definitions.
+    fn test_registry() -> BlockRegistry {
+        BlockRegistry::from_definitions(ron::from_str(TEST_BLOCKS).unwrap()).unwrap()
+    }
+
+
+    /// Creates a world holding a single fully skylit chunk at the origin.
+    fn single_chunk_world(mut chunk: Chunk) -> WorldChunks {
+        chunk.fill_light(LightChannel::Sky, MAX_LIGHT);
+        let mut world = WorldChunks::default();
+        world.chunks.insert(IVec3::ZERO, chunk);
+        world
+    }
+
+
+    /// Splits the opaque mesh of a chunk into quads.
+    fn mesh_quads(meshing_mode: MeshingMode, world: &WorldChunks, registry: &BlockRegistry) -> Vec<Quad> {
+        let mesh = build_mesh_with(meshing_mode, IVec3::ZERO, world, registry).opaque;
+        let Some(VertexAttributeValues::Float32x3(positions)) = mesh.attribute(Mesh::ATTRIBUTE_POSITION) else { panic!("mesh has no positions"); };
+        let Some(VertexAttributeValues::Float32x2(uvs)) = mesh.attribute(Mesh::ATTRIBUTE_UV_0) else { panic!("mesh has no uvs"); };
+        let Some(VertexAttributeValues::Float32x2(tiles)) = mesh.attribute(Mesh::ATTRIBUTE_UV_1) else { panic!("mesh has no tiles"); };
+
+        positions
+            .chunks_exact(4)
+            .zip(uvs.chunks_exact(4))
+            .zip(tiles.chunks_exact(4))
+            .map(|((corners, uvs), tiles)| {
+                assert!(tiles.iter().all(|tile| *tile == tiles[0]), "quad corners sample different tiles");
+                Quad { corners: corners.try_into().unwrap(), uvs: uvs.try_into().unwrap(), tile: tiles[0] }
+            })
+            .collect()
+    }
+
+
+    /// Sums the area of the quads drawn with each atlas tile.
+    fn area_by_tile(quads: &[Quad]) -> BTreeMap<[u32; 2], f32> {
+        let mut areas = BTreeMap::new();
+        for quad in quads {
+            let area = edge_length(quad.corners[0], quad.corners[1]) * edge_length(quad.corners[1], quad.corners[2]);
+            *areas.entry(quad.tile.map(f32::to_bits)).or_insert(0.0) += area;
         }
-    } 
-    else if !chunk.blocks[Chunk::get_index(block_x, block_y, block_z - 1)].is_solid() {
-        visible_faces |= BlockFaces::BACK_FACE;
+        areas
     }
 
-    if block_x == 0 {
-        if let Some(neighbor) = world.chunks.get(&(chunk_position + CHUNK_NEIGHBOURS[2])) {
-            if !neighbor.blocks[Chunk::get_index(CHUNK_SIZE_X - 1, block_y, block_z)].is_solid() {
-                visible_faces |= BlockFaces::LEFT_FACE;
+
+    /// Checks that every quad repeats its atlas tile exactly once per block along both edges.
+    fn assert_uvs_tile_per_block(quads: &[Quad]) {
+        for quad in quads {
+            for (start, end) in [(0, 1), (1, 2)] {
+                let edge = edge_length(quad.corners[start], quad.corners[end]);
+                let uv_edge = (quad.uvs[end][0] - quad.uvs[start][0]).abs() + (quad.uvs[end][1] - quad.uvs[start][1]).abs();
+                assert!((edge - uv_edge).abs() < 1e-5, "quad edge of {edge} blocks spans {uv_edge} tiles");
             }
-        } 
-        else {
-            visible_faces |= BlockFaces::LEFT_FACE;
         }
-    } 
-    else if !chunk.blocks[Chunk::get_index(block_x - 1, block_y, block_z)].is_solid() {
-        visible_faces |= BlockFaces::LEFT_FACE;
     }
 
-    if block_x + 1 >= CHUNK_SIZE_X {
-        if let Some(neighbor) = world.chunks.get(&(chunk_position + CHUNK_NEIGHBOURS[3])) {
-            if !neighbor.blocks[Chunk::get_index(0, block_y, block_z)].is_solid() {
-                visible_faces |= BlockFaces::RIGHT_FACE;
-            }
-        } 
-        else {
-            visible_faces |= BlockFaces::RIGHT_FACE;
+
+    /// Meshes a chunk both ways and checks both meshes cover the same faces with correctly tiled uvs.
+    fn compare_meshing_modes(chunk: Chunk) -> (usize, usize) {
+        let registry = test_registry();
+        let world = single_chunk_world(chunk);
+        let naive = mesh_quads(MeshingMode::Naive, &world, &registry);
+        let greedy = mesh_quads(MeshingMode::Greedy, &world, &registry);
+
+        let naive_areas = area_by_tile(&naive);
+        let greedy_areas = area_by_tile(&greedy);
+        assert_eq!(naive_areas.keys().collect::<Vec<_>>(), greedy_areas.keys().collect::<Vec<_>>());
+        for (tile, naive_area) in &naive_areas {
+            assert!((naive_area - greedy_areas[tile]).abs() < 1e-3, "covered area differs for tile {tile:?}");
         }
-    } 
-    else if !chunk.blocks[Chunk::get_index(block_x + 1, block_y, block_z)].is_solid() {
-        visible_faces |= BlockFaces::RIGHT_FACE;
+
+        assert_uvs_tile_per_block(&naive);
+        assert_uvs_tile_per_block(&greedy);
+        assert!(greedy.len() <= naive.len());
+
+        (naive.len(), greedy.len())
     }
 
-    if block_y + 1 >= CHUNK_SIZE_Y {
-        if let Some(neighbor) = world.chunks.get(&(chunk_position + CHUNK_NEIGHBOURS[4])) {
-            if !neighbor.blocks[Chunk::get_index(block_x, 0, block_z)].is_solid() {
-                visible_faces |= BlockFaces::TOP_FACE;
+
+    #[test]
+    fn greedy_merges_a_flat_slab() {
+        let stone = test_registry().block("stone");
+        let mut chunk = Chunk::new();
+        for block_z in 0..CHUNK_SIZE_Z {
+            for block_x in 0..CHUNK_SIZE_X {
+                chunk.set(block_x, 0, block_z, stone);
             }
-        } 
-        else {
-            visible_faces |= BlockFaces::TOP_FACE;
         }
-    } 
-    else if !chunk.blocks[Chunk::get_index(block_x, block_y + 1, block_z)].is_solid() {
-        visible_faces |= BlockFaces::TOP_FACE;
+
+        let (naive_quads, greedy_quads) = compare_meshing_modes(chunk);
+        assert!(greedy_quads * 50 < naive_quads, "greedy made {greedy_quads} quads against {naive_quads} naive quads");
     }
 
-    if block_y == 0 {
-        if let Some(neighbor) = world.chunks.get(&(chunk_position + CHUNK_NEIGHBOURS[5])) {
-            if !neighbor.blocks[Chunk::get_index(block_x, CHUNK_SIZE_Y - 1, block_z)].is_solid() {
-                visible_faces |= BlockFaces::BOTTOM_FACE;
+
+    #[test]
+    fn greedy_matches_naive_on_a_checkerboard() {
+        let stone = test_registry().block("stone");
+        let mut chunk = Chunk::new();
+        for (block_index, (block_x, block_y, block_z)) in GET_COORDS.iter().enumerate() {
+            if (block_x + block_y + block_z) % 2 == 0 {
+                chunk.blocks.set(block_index, stone);
             }
-        } 
-        else {
-            visible_faces |= BlockFaces::BOTTOM_FACE;
         }
-    } 
-    else if !chunk.blocks[Chunk::get_index(block_x, block_y - 1, block_z)].is_solid() {
-        visible_faces |= BlockFaces::BOTTOM_FACE;
+
+        compare_meshing_modes(chunk);
     }
 
-    visible_faces
-}
\ No newline at end of file
+
+    #[test]
+    fn greedy_matches_naive_on_mixed_blocks() {
+        let registry = test_registry();
+        let blocks = [Block::AIR, registry.block("stone"), registry.block("dirt"), registry.block("grass")];
+        let mut chunk = Chunk::new();
+        for (block_index, (block_x, block_y, block_z)) in GET_COORDS.iter().enumerate() {
+            let world_position = IVec3::new(*block_x as i32, *block_y as i32, *block_z as i32);
+            let block = blocks[(hash_position(7, world_position) % 8).min(3) as usize];
+            chunk.blocks.set(block_index, block);
+        }
+
+        compare_meshing_modes(chunk);
+    }
+}
@@ -0,0 +1,268 @@
+use std::collections::HashSet;
+use std::collections::HashMap;
+use bevy::tasks::futures::check_ready;
+use bevy::tasks::AsyncComputeTaskPool;
+use bevy::asset::RenderAssetUsages;
+use bevy::mesh::PrimitiveTopology;
+use bevy::tasks::Task;
+use bevy::mesh::Indices;
+use bevy::prelude::*;
+use glam::IVec3;
+
+use crate::generator::*;
+use crate::registry::*;
+use crate::player::*;
+use crate::chunk::*;
+use crate::world::*;
+
+
+// Chunks along each side of a far terrain tile and the blocks between its height samples
+const FAR_TILE_CHUNKS: i32 = 4;
+const FAR_SAMPLE_SPACING: i32 = 8;
+
+// Far terrain reaches this many times the render distance
+const FAR_DISTANCE_FACTOR: i32 = 4;
+
+// Far terrain sits slightly below the sampled heights so it stays under the real surface at each sample,
+// quads over spawned chunk columns are clipped so it never has to line up with real terrain between samples
+const FAR_TERRAIN_SINK: f32 = 2.0;
+
+// Quads along each side of a chunk column, sample spacing divides the chunk size
+const COLUMN_QUADS: usize = CHUNK_SIZE_X / FAR_SAMPLE_SPACING as usize;
+
+// Tiles sampled on the async compute pool at once
+const MAX_FAR_TILE_TASKS: usize = 8;
+
+
+// Sampling of one far terrain tile, finishing with its mesh and quads or None when it has no ground
+pub type FarTileTask = Task<Option<(Mesh, Vec<TileQuad>)>>;
+
+
+// Low poly heightfield tiles drawn beyond the loaded chunks, keyed by tile position with y always zero
+#[derive(Resource, Default)]
+pub struct FarTerrain {
+    pub tiles: HashMap<IVec3, Entity>,
+    pub tasks: HashMap<IVec3, FarTileTask>,
+    pub material: Option<Handle<StandardMaterial>>,
+}
+
+
+// Triangle indices of one far terrain quad and the chunk column of its tile it lies over
+#[derive(Clone, Copy)]
+pub struct TileQuad {
+    pub column: usize,
+    pub indices: [u32; 6],
+}
+
+
+// Quads of a far terrain tile and the chunk columns currently clipped out of it, one bit per column
+#[derive(Component)]
+pub struct FarTerrainTile {
+    pub quads: Vec<TileQuad>,
+    pub clipped_columns: Option<u32>,
+}
+
+
+/// Returns the world block position of a tiles corner.
+fn get_tile_origin(tile_position: IVec3) -> IVec3 {
+    tile_position * IVec3::new(FAR_TILE_CHUNKS * CHUNK_SIZE_X as i32, 0, FAR_TILE_CHUNKS * CHUNK_SIZE_Z as i32)
+}
+
+
+/// Returns the chunk columns a tile covers in column index order, with y always zero.
+fn tile_columns(tile_position: IVec3) -> impl Iterator<Item = IVec3> {
+    let first_column = tile_position * FAR_TILE_CHUNKS;
+    (0..FAR_TILE_CHUNKS).flat_map(move |z| (0..FAR_TILE_CHUNKS).map(move |x| first_column + IVec3::new(x, 0, z)))
+}
+
+
+/// Returns the indices of every quad not lying over a clipped chunk column.
+fn visible_indices(quads: &[TileQuad], clipped_columns: u32) -> Vec<u32> {
+    quads
+        .iter()
+        .filter(|quad| clipped_columns & (1 << quad.column) == 0)
+        .flat_map(|quad| quad.indices)
+        .collect()
+}
+
+
+/// Samples generator heights over a tile and builds a heightfield colored by each columns top block.
+fn build_tile_mesh(tile_position: IVec3, generator: &dyn WorldGenerator, seed: u32, registry: &BlockRegistry) -> Option<(Mesh, Vec<TileQuad>)> {
+    let tile_origin = get_tile_origin(tile_position);
+    let samples_per_side = (FAR_TILE_CHUNKS * CHUNK_SIZE_X as i32 / FAR_SAMPLE_SPACING + 1) as usize;
+
+    let mut positions = Vec::with_capacity(samples_per_side * samples_per_side);
+    let mut colors = Vec::with_capacity(samples_per_side * samples_per_side);
+    let mut has_ground = Vec::with_capacity(samples_per_side * samples_per_side);
+    for sample_z in 0..samples_per_side {
+        for sample_x in 0..samples_per_side {
+            let offset_x = sample_x as i32 * FAR_SAMPLE_SPACING;
+            let offset_z = sample_z as i32 * FAR_SAMPLE_SPACING;
+            let surface = generator.surface_at(tile_origin.x + offset_x, tile_origin.z + offset_z, seed, registry);
+
+            // The top face of the surface block is one above its height
+            let (height, color) = match surface {
+                Some((height, block)) => {
+                    let [red, green, blue] = registry.get(block).color;
+                    (height as f32 + 1.0 - FAR_TERRAIN_SINK, Color::srgb_u8(red, green, blue).to_linear().to_f32_array())
+                }
+                None => (0.0, [0.0; 4]),
+            };
+
+            positions.push([offset_x as f32, height, offset_z as f32]);
+            colors.push(color);
+            has_ground.push(surface.is_some());
+        }
+    }
+
+    // Quads touching a sample without ground are left open
+    let mut quads = Vec::new();
+    for sample_z in 0..samples_per_side - 1 {
+        for sample_x in 0..samples_per_side - 1 {
+            let corner = (sample_z * samples_per_side + sample_x) as u32;
+            let quad = [corner, corner + samples_per_side as u32, corner + samples_per_side as u32 + 1, corner + 1];
+            if quad.iter().any(|index| !has_ground[*index as usize]) {
+                continue;
+            }
+
+            quads.push(TileQuad {
+                column: sample_z / COLUMN_QUADS * FAR_TILE_CHUNKS as usize + sample_x / COLUMN_QUADS,
+                indices: [quad[0], quad[1], quad[2], quad[0], quad[2], quad[3]],
+            });
+        }
+    }
+
+    if quads.is_empty() {
+        return None;
+    }
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::default());
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+    mesh.insert_indices(Indices::U32(visible_indices(&quads, 0)));
+    mesh.compute_smooth_normals();
+
+    Some((mesh, quads))
+}
+
+
+/// Creates the vertex colored material shared by every far terrain tile.
+pub fn setup_far_terrain(
+    mut far_terrain: ResMut<FarTerrain>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    far_terrain.material = Some(materials.add(StandardMaterial {
+        base_color: Color::WHITE,
+        perceptual_roughness: 1.0,
+        ..Default::default()
+    }));
+}
+
+
+/// Samples far terrain tiles around the player, drops tiles out of range and clips tiles where chunks have spawned.
+pub fn update_far_terrain(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut far_terrain: ResMut<FarTerrain>,
+    mut tile_query: Query<(&mut FarTerrainTile, &mut Visibility, Option<&Mesh3d>)>,
+    player: Query<&Transform, With<Player>>,
+    chunk_entities: Res<ChunkEntities>,
+    chunk_generator: Res<ChunkGenerator>,
+    world_seed: Res<WorldSeed>,
+    registry: Res<BlockRegistry>,
+) {
+    let Ok(player_transform) = player.single() else { return; };
+    let Some(material) = far_terrain.material.clone() else { return; };
+    let far_terrain = &mut *far_terrain;
+
+    let player_chunk = get_chunk_position(player_transform.translation);
+    let player_tile = IVec3::new(player_chunk.x.div_euclid(FAR_TILE_CHUNKS), 0, player_chunk.z.div_euclid(FAR_TILE_CHUNKS));
+    let tile_distance = RENDER_DISTANCE * FAR_DISTANCE_FACTOR / FAR_TILE_CHUNKS;
+    let in_range = |tile_position: &IVec3| (*tile_position - player_tile).abs().max_element() <= tile_distance;
+
+    // Tiles that fell out of range are despawned, their unfinished tasks cancelled by dropping them
+    far_terrain.tiles.retain(|tile_position, entity| {
+        let keep = in_range(tile_position);
+        if !keep {
+            commands.entity(*entity).despawn();
+        }
+        keep
+    });
+    far_terrain.tasks.retain(|tile_position, _task| in_range(tile_position));
+
+    // Start sampling missing tiles nearest first
+    let mut missing: Vec<IVec3> = (-tile_distance..=tile_distance)
+        .flat_map(|x| (-tile_distance..=tile_distance).map(move |z| player_tile + IVec3::new(x, 0, z)))
+        .filter(|tile_position| !far_terrain.tiles.contains_key(tile_position) && !far_terrain.tasks.contains_key(tile_position))
+        .collect();
+    missing.sort_by_key(|tile_position| (*tile_position - player_tile).abs().max_element());
+
+    let task_pool = AsyncComputeTaskPool::get();
+    for tile_position in missing.into_iter().take(MAX_FAR_TILE_TASKS.saturating_sub(far_terrain.tasks.len())) {
+        let generator = chunk_generator.0.clone();
+        let registry = registry.clone();
+        let seed = world_seed.0;
+        let task = task_pool.spawn(async move {
+            build_tile_mesh(tile_position, generator.as_ref(), seed, &registry)
+        });
+        far_terrain.tasks.insert(tile_position, task);
+    }
+
+    // Spawn finished tiles, tiles without ground still get an entity so they are not sampled again
+    let mut finished = Vec::new();
+    far_terrain.tasks.retain(|tile_position, task| {
+        match check_ready(task) {
+            Some(mesh) => {
+                finished.push((*tile_position, mesh));
+                false
+            }
+            None => true,
+        }
+    });
+
+    for (tile_position, tile) in finished {
+        let tile_origin = get_tile_origin(tile_position);
+        let mut tile_entity = commands.spawn((
+            Transform::from_translation(Vec3::new(tile_origin.x as f32, 0.0, tile_origin.z as f32)),
+            GlobalTransform::default(),
+            Visibility::Hidden,
+        ));
+        match tile {
+            Some((mesh, quads)) => {
+                tile_entity.insert((
+                    FarTerrainTile { quads, clipped_columns: None },
+                    Mesh3d(meshes.add(mesh)),
+                    MeshMaterial3d(material.clone()),
+                ));
+            }
+            None => {
+                tile_entity.insert(FarTerrainTile { quads: Vec::new(), clipped_columns: None });
+            }
+        }
+        far_terrain.tiles.insert(tile_position, tile_entity.id());
+    }
+
+    // Real chunks replace the quads over their column as soon as it has spawned
+    let spawned_columns: HashSet<IVec3> = chunk_entities.map
+        .keys()
+        .map(|chunk_position| IVec3::new(chunk_position.x, 0, chunk_position.z))
+        .collect();
+
+    for (tile_position, entity) in &far_terrain.tiles {
+        let Ok((mut tile, mut visibility, mesh_handle)) = tile_query.get_mut(*entity) else { continue; };
+        let clipped_columns = tile_columns(*tile_position)
+            .enumerate()
+            .filter(|(_column_index, column)| spawned_columns.contains(column))
+            .fold(0, |clipped, (column_index, _column)| clipped | (1 << column_index));
+        if tile.clipped_columns == Some(clipped_columns) {
+            continue;
+        }
+        tile.clipped_columns = Some(clipped_columns);
+
+        let indices = visible_indices(&tile.quads, clipped_columns);
+        visibility.set_if_neq(if indices.is_empty() { Visibility::Hidden } else { Visibility::Inherited });
+        if let Some(mesh) = mesh_handle.and_then(|mesh_handle| meshes.get_mut(&mesh_handle.0)) {
+            mesh.insert_indices(Indices::U32(indices));
+        }
+    }
+}
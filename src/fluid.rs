@@ -0,0 +1,183 @@
+use std::collections::HashSet;
+use std::time::Duration;
+use bevy::prelude::*;
+use glam::IVec3;
+
+use crate::registry::*;
+use crate::region::*;
+use crate::block::*;
+use crate::world::*;
+
+
+const FLUID_TICK: Duration = Duration::from_millis(250);
+const MAX_FLUID_UPDATES: usize = 1024;
+const HORIZONTAL_NEIGHBOURS: [IVec3; 4] = [
+    IVec3::new(0, 0, 1),
+    IVec3::new(0, 0, -1),
+    IVec3::new(-1, 0, 0),
+    IVec3::new(1, 0, 0),
+];
+
+// Fluid states, sources are full blocks, flowing fluid thins out with distance and falling fluid fills its block
+pub const SOURCE_LEVEL: u8 = 0;
+pub const MAX_FLOW_LEVEL: u8 = 7;
+pub const FALLING_LEVEL: u8 = 8;
+
+
+// Blocks to re-evaluate on the next fluid tick
+#[derive(Resource)]
+pub struct FluidTicks {
+    pub timer: Timer,
+    pub pending: HashSet<IVec3>,
+}
+
+
+impl FluidTicks {
+    /// Sets the default fluid tick rate.
+    pub fn default() -> Self {
+        Self {
+            timer: Timer::new(FLUID_TICK, TimerMode::Repeating),
+            pending: HashSet::new(),
+        }
+    }
+
+
+    /// Schedules a changed block and its neighbours for the next fluid tick.
+    pub fn schedule_around(&mut self, block_position: IVec3) {
+        self.pending.insert(block_position);
+        for direction in CHUNK_NEIGHBOURS {
+            self.pending.insert(block_position + direction);
+        }
+    }
+
+
+    /// Schedules fluid blocks of a newly loaded chunk and its loaded neighbours that would still change.
+    pub fn schedule_loaded_chunk(&mut self, world: &WorldChunks, registry: &BlockRegistry, chunk_position: IVec3) {
+        let chunks = std::iter::once(chunk_position).chain(CHUNK_NEIGHBOURS.map(|direction| chunk_position + direction));
+
+        for loaded_chunk in chunks {
+            for block_position in world.find_blocks(loaded_chunk, |block| registry.is_fluid(block)) {
+                let unsettled = std::iter::once(block_position)
+                    .chain(CHUNK_NEIGHBOURS.map(|direction| block_position + direction))
+                    .any(|position| next_fluid_state(world, registry, position).is_some());
+                if unsettled {
+                    self.schedule_around(block_position);
+                }
+            }
+        }
+    }
+}
+
+
+/// Returns the surface height of a fluid block with the given state.
+pub fn fluid_height(state: u8) -> f32 {
+    if state >= FALLING_LEVEL {
+        return 1.0;
+    }
+
+    (MAX_FLOW_LEVEL + 1 - state) as f32 / (MAX_FLOW_LEVEL + 2) as f32
+}
+
+
+/// Returns true when fluid can flow into the block, replacing it.
+fn is_replaceable(registry: &BlockRegistry, block: Block) -> bool {
+    block == Block::AIR || (registry.is_fluid(block) && block.state != SOURCE_LEVEL)
+}
+
+
+/// Returns the state a block should take on this tick, or None when it stays the same.
+fn next_fluid_state(world: &WorldChunks, registry: &BlockRegistry, block_position: IVec3) -> Option<Block> {
+    let block = world.get_block(block_position)?;
+
+    // Sources stay until they are replaced, everything else except air and flowing fluid is left alone
+    if !is_replaceable(registry, block) {
+        return None;
+    }
+
+    let below = world.get_block(block_position + IVec3::NEG_Y);
+    let fluid_at = |direction: IVec3| {
+        world.get_block(block_position + direction).filter(|neighbor| registry.is_fluid(*neighbor))
+    };
+
+    // Fluid above always falls into this block
+    let next = if let Some(above) = fluid_at(IVec3::Y) {
+        above.with_state(FALLING_LEVEL)
+    }
+    else {
+        let mut sources = 0;
+        let mut feeder: Option<Block> = None;
+
+        for direction in HORIZONTAL_NEIGHBOURS {
+            let Some(neighbor) = fluid_at(direction) else { continue; };
+            if neighbor.state == SOURCE_LEVEL {
+                sources += 1;
+            }
+
+            // Flowing fluid only spreads sideways once it can no longer fall
+            let neighbor_below = world.get_block(block_position + direction + IVec3::NEG_Y);
+            let supported = neighbor.state == SOURCE_LEVEL
+                || neighbor_below.is_some_and(|below| !is_replaceable(registry, below));
+            if !supported {
+                continue;
+            }
+
+            let level = if neighbor.state >= FALLING_LEVEL { 1 } else { neighbor.state + 1 };
+            if level <= MAX_FLOW_LEVEL && feeder.is_none_or(|feeder| level < feeder.state) {
+                feeder = Some(neighbor.with_state(level));
+            }
+        }
+
+        // Two neighbouring sources over a solid floor or another source make an infinite source
+        let on_floor = below.is_some_and(|below| {
+            !is_replaceable(registry, below) && (!registry.is_fluid(below) || below.state == SOURCE_LEVEL)
+        });
+
+        match feeder {
+            Some(feeder) if sources >= 2 && on_floor => feeder.with_state(SOURCE_LEVEL),
+            Some(feeder) => feeder,
+            None => Block::AIR,
+        }
+    };
+
+    (next != block).then_some(next)
+}
+
+
+/// Spreads and drains fluids on a fixed tick, remeshing the chunks they changed.
+pub fn tick_fluids(
+    time: Res<Time>,
+    registry: Res<BlockRegistry>,
+    mut fluid_ticks: ResMut<FluidTicks>,
+    mut world: ResMut<WorldChunks>,
+    mut chunk_tasks: ResMut<ChunkTasks>,
+    mut world_save: ResMut<WorldSave>,
+) {
+    if !fluid_ticks.timer.tick(time.delta()).just_finished() {
+        return;
+    }
+
+    let batch: Vec<IVec3> = fluid_ticks.pending.iter().take(MAX_FLUID_UPDATES).copied().collect();
+    for block_position in &batch {
+        fluid_ticks.pending.remove(block_position);
+    }
+
+    // Every block reads the world as it was at the start of the tick
+    let changes: Vec<(IVec3, Block)> = batch
+        .into_iter()
+        .filter_map(|block_position| {
+            next_fluid_state(&world, &registry, block_position).map(|block| (block_position, block))
+        })
+        .collect();
+
+    // Fluids let light through like air so only meshes need rebuilding
+    let mut changed_chunks = HashSet::new();
+    for (block_position, block) in changes {
+        let Some(chunk_position) = world.set_block(block_position, block) else { continue; };
+        world_save.modified.insert(chunk_position);
+        changed_chunks.extend(chunks_sampling_block(block_position));
+        fluid_ticks.schedule_around(block_position);
+    }
+
+    changed_chunks.retain(|chunk_position| world.chunks.contains_key(chunk_position));
+    chunk_tasks.remesh_all(changed_chunks);
+}
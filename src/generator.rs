@@ -0,0 +1,338 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+use bevy::prelude::*;
+use std::sync::Arc;
+use glam::IVec3;
+
+use crate::heightmap::*;
+use crate::registry::*;
+use crate::terrain::*;
+use crate::chunk::*;
+use crate::block::*;
+use crate::biome::*;
+use crate::cave::*;
+
+
+const DEFAULT_FLAT_LAYERS: &str = "bedrock,2*dirt,grass";
+
+// World height of the lowest superflat layer
+const FLAT_BASE_HEIGHT: i32 = 0;
+
+// Half width and height of the stone platform a void world spawns the player onto
+const VOID_PLATFORM_RADIUS: i32 = 2;
+const VOID_PLATFORM_HEIGHT: i32 = 0;
+
+
+// Fills chunks with blocks, the seed keeps the result reproducible
+pub trait WorldGenerator: Send + Sync {
+    /// Generates the blocks of the chunk at the given position.
+    fn generate(&self, chunk_position: &IVec3, seed: u32, registry: &BlockRegistry) -> Chunk;
+
+
+    /// Returns the surface height and top block of a column without generating its chunk, None where there is no ground.
+    fn surface_at(&self, _world_x: i32, _world_z: i32, _seed: u32, _registry: &BlockRegistry) -> Option<(i32, Block)> {
+        None
+    }
+
+
+    /// Returns the biome of a column, None for generators without biomes.
+    fn biome_at(&self, _world_x: i32, _world_z: i32, _seed: u32) -> Option<Biome> {
+        None
+    }
+
+
+    /// Returns the name saves of this generator are kept under, None keeps them directly in the seed directory.
+    fn save_name(&self) -> Option<&str> {
+        None
+    }
+
+
+    /// Replaces the terrain noise settings, generators without terrain noise ignore them.
+    fn set_terrain_settings(&self, _terrain_settings: &TerrainNoiseSettings) {}
+}
+
+
+// Generator used for every chunk, cheap to clone into async tasks
+#[derive(Resource, Clone)]
+pub struct ChunkGenerator(pub Arc<dyn WorldGenerator>);
+
+
+// Biome shaped noise terrain with caves and structures, the terrain noise is built once per seed
+// and rebuilt whenever the terrain settings change
+pub struct NoiseGenerator {
+    pub cave_settings: CaveSettings,
+    terrain_blocks: TerrainBlocks,
+    terrain_settings: RwLock<TerrainNoiseSettings>,
+    terrain_noise: RwLock<HashMap<u32, Arc<TerrainNoise>>>,
+}
+
+
+// Horizontal layers of blocks repeated in every column, listed bottom to top
+pub struct FlatGenerator {
+    pub layers: Vec<Block>,
+    save_name: String,
+}
+
+
+// Empty world apart from a small platform to stand on
+pub struct VoidGenerator {
+    platform: Block,
+}
+
+
+impl ChunkGenerator {
+    /// Picks the generator from the --generator argument, such as noise, void, flat:bedrock,3*dirt,grass or heightmap:maps/island.png.
+    pub fn from_args(registry: &BlockRegistry, terrain_settings: &TerrainNoiseSettings) -> Result<Self, String> {
+        let arguments: Vec<String> = std::env::args().collect();
+        let generator = arguments
+            .windows(2)
+            .find(|pair| pair[0] == "--generator")
+            .map(|pair| pair[1].as_str());
+
+        let (name, options) = match generator {
+            Some(generator) => generator.split_once(':').unwrap_or((generator, "")),
+            None => ("noise", ""),
+        };
+
+        let generator: Arc<dyn WorldGenerator> = match name {
+            "noise" => Arc::new(NoiseGenerator::new(terrain_settings.clone(), registry)?),
+            "void" => Arc::new(VoidGenerator::new(registry)?),
+            "flat" if options.is_empty() => Arc::new(FlatGenerator::from_layers(DEFAULT_FLAT_LAYERS, registry)?),
+            "flat" => Arc::new(FlatGenerator::from_layers(options, registry)?),
+            "heightmap" => Arc::new(HeightmapGenerator::from_options(options, registry)?),
+            _ => return Err(format!("unknown world generator {name}")),
+        };
+
+        Ok(Self(generator))
+    }
+}
+
+
+impl NoiseGenerator {
+    /// Creates a noise generator with the given terrain settings and default caves, failing when a terrain block is missing.
+    pub fn new(terrain_settings: TerrainNoiseSettings, registry: &BlockRegistry) -> Result<Self, String> {
+        Ok(Self {
+            cave_settings: CaveSettings::default(),
+            terrain_blocks: TerrainBlocks::resolve(registry)?,
+            terrain_settings: RwLock::new(terrain_settings),
+            terrain_noise: RwLock::new(HashMap::new()),
+        })
+    }
+
+
+    /// Runs a function with the terrain noise of a seed, building it the first time the seed is seen.
+    fn with_terrain_noise<R>(&self, seed: u32, function: impl FnOnce(&TerrainNoise) -> R) -> R {
+        let cached = self.terrain_noise.read().unwrap().get(&seed).cloned();
+        let terrain_noise = match cached {
+            Some(terrain_noise) => terrain_noise,
+            None => {
+                let terrain_settings = self.terrain_settings.read().unwrap();
+                let mut terrain_noise = self.terrain_noise.write().unwrap();
+                terrain_noise
+                    .entry(seed)
+                    .or_insert_with(|| Arc::new(TerrainNoise::new(seed, &terrain_settings)))
+                    .clone()
+            }
+        };
+
+        function(&terrain_noise)
+    }
+}
+
+
+impl WorldGenerator for NoiseGenerator {
+    fn generate(&self, chunk_position: &IVec3, seed: u32, _registry: &BlockRegistry) -> Chunk {
+        self.with_terrain_noise(seed, |terrain_noise| {
+            prepare_geometry(chunk_position, seed, &self.terrain_blocks, terrain_noise, &self.cave_settings)
+        })
+    }
+
+
+    fn surface_at(&self, world_x: i32, world_z: i32, seed: u32, _registry: &BlockRegistry) -> Option<(i32, Block)> {
+        let column = self.with_terrain_noise(seed, |terrain_noise| terrain_noise.column_at(world_x, world_z));
+        Some((column.height, self.terrain_blocks.surface(column.biome)))
+    }
+
+
+    fn biome_at(&self, world_x: i32, world_z: i32, seed: u32) -> Option<Biome> {
+        Some(self.with_terrain_noise(seed, |terrain_noise| terrain_noise.biome_at(world_x, world_z)))
+    }
+
+
+    fn set_terrain_settings(&self, terrain_settings: &TerrainNoiseSettings) {
+        // Both locks are taken in the same order as when building noise, so nothing cached from the old settings survives
+        let mut current_settings = self.terrain_settings.write().unwrap();
+        let mut terrain_noise = self.terrain_noise.write().unwrap();
+        *current_settings = terrain_settings.clone();
+        terrain_noise.clear();
+    }
+}
+
+
+impl FlatGenerator {
+    /// Parses a comma separated layer list where each entry is a block name, optionally prefixed by a count and *.
+    pub fn from_layers(layers: &str, registry: &BlockRegistry) -> Result<Self, String> {
+        let mut blocks = Vec::new();
+
+        for entry in layers.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+            let (count, name) = match entry.split_once('*') {
+                Some((count, name)) => {
+                    let count = count.trim().parse::<usize>()
+                        .map_err(|_| format!("invalid layer count in {entry}"))?;
+                    (count, name.trim())
+                }
+                None => (1, entry),
+            };
+
+            let block = registry.require(name).map_err(|_| format!("unknown layer block {name}"))?;
+            blocks.extend(std::iter::repeat_n(block, count));
+        }
+
+        // Saves are kept per layer list, with repeated layers written as one run so equal lists share saves
+        let mut save_name = String::from("flat");
+        for run in blocks.chunk_by(|below, above| below == above) {
+            save_name.push_str(&format!("_{}x{}", run.len(), sanitize_save_name(&registry.get(run[0]).name)));
+        }
+
+        Ok(Self { layers: blocks, save_name })
+    }
+}
+
+
+impl WorldGenerator for FlatGenerator {
+    fn generate(&self, chunk_position: &IVec3, _seed: u32, _registry: &BlockRegistry) -> Chunk {
+        let mut chunk = Chunk::new();
+        let base_y = chunk_position.y * CHUNK_SIZE_Y as i32;
+
+        for block_y in 0..CHUNK_SIZE_Y {
+            let layer = base_y + block_y as i32 - FLAT_BASE_HEIGHT;
+            let Some(block) = usize::try_from(layer).ok().and_then(|layer| self.layers.get(layer)) else { continue; };
+
+            for block_z in 0..CHUNK_SIZE_Z {
+                for block_x in 0..CHUNK_SIZE_X {
+                    chunk.set(block_x, block_y, block_z, *block);
+                }
+            }
+        }
+
+        chunk.compact();
+        chunk
+    }
+
+
+    fn surface_at(&self, _world_x: i32, _world_z: i32, _seed: u32, _registry: &BlockRegistry) -> Option<(i32, Block)> {
+        let top_block = *self.layers.last()?;
+        Some((FLAT_BASE_HEIGHT + self.layers.len() as i32 - 1, top_block))
+    }
+
+
+    fn save_name(&self) -> Option<&str> {
+        Some(&self.save_name)
+    }
+}
+
+
+impl VoidGenerator {
+    /// Creates a void generator whose platform is made of stone, failing when the registry has no stone.
+    pub fn new(registry: &BlockRegistry) -> Result<Self, String> {
+        Ok(Self { platform: registry.require("stone")? })
+    }
+}
+
+
+impl WorldGenerator for VoidGenerator {
+    fn generate(&self, chunk_position: &IVec3, _seed: u32, _registry: &BlockRegistry) -> Chunk {
+        let mut chunk = Chunk::new();
+        let chunk_size = IVec3::new(CHUNK_SIZE_X as i32, CHUNK_SIZE_Y as i32, CHUNK_SIZE_Z as i32);
+        let chunk_min = *chunk_position * chunk_size;
+
+        for world_z in -VOID_PLATFORM_RADIUS..=VOID_PLATFORM_RADIUS {
+            for world_x in -VOID_PLATFORM_RADIUS..=VOID_PLATFORM_RADIUS {
+                let local = IVec3::new(world_x, VOID_PLATFORM_HEIGHT, world_z) - chunk_min;
+                if local.cmplt(IVec3::ZERO).any() || local.cmpge(chunk_size).any() {
+                    continue;
+                }
+
+                chunk.set(local.x as usize, local.y as usize, local.z as usize, self.platform);
+            }
+        }
+
+        chunk.compact();
+        chunk
+    }
+
+
+    fn save_name(&self) -> Option<&str> {
+        Some("void")
+    }
+}
+
+
+/// Hands changed terrain settings to the generator so chunks generated from now on use the new noise.
+pub fn apply_terrain_settings(
+    terrain_settings: Res<TerrainNoiseSettings>,
+    chunk_generator: Res<ChunkGenerator>,
+) {
+    if !terrain_settings.is_changed() || terrain_settings.is_added() {
+        return;
+    }
+
+    chunk_generator.0.set_terrain_settings(&terrain_settings);
+}
+
+
+/// Replaces every character that is not safe in a directory name with an underscore.
+pub fn sanitize_save_name(name: &str) -> String {
+    name.chars()
+        .map(|character| if character.is_ascii_alphanumeric() || character == '-' { character } else { '_' })
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    /// Loads the blocks the game ships with.
+    fn game_registry() -> BlockRegistry {
+        BlockRegistry::load(resolve_game_path(BLOCK_REGISTRY_PATH)).unwrap()
+    }
+
+
+    #[test]
+    fn noise_chunks_are_the_same_for_the_same_seed() {
+        let registry = game_registry();
+        let seed = 213123;
+
+        // Separate generators so nothing cached by the first run can leak into the second
+        let first = NoiseGenerator::new(TerrainNoiseSettings::default(), &registry).unwrap();
+        let second = NoiseGenerator::new(TerrainNoiseSettings::default(), &registry).unwrap();
+
+        for chunk_position in [IVec3::new(0, 0, 0), IVec3::new(-3, 0, 5), IVec3::new(7, -1, -2)] {
+            let expected = first.generate(&chunk_position, seed, &registry);
+            let repeated = first.generate(&chunk_position, seed, &registry);
+            let fresh = second.generate(&chunk_position, seed, &registry);
+
+            for block_index in 0..CHUNK_VOLUME {
+                assert!(
+                    repeated.get_at(block_index) == expected.get_at(block_index) && fresh.get_at(block_index) == expected.get_at(block_index),
+                    "chunk {chunk_position} differs at block index {block_index}",
+                );
+            }
+        }
+    }
+
+
+    #[test]
+    fn noise_chunks_differ_between_seeds() {
+        let registry = game_registry();
+        let generator = NoiseGenerator::new(TerrainNoiseSettings::default(), &registry).unwrap();
+        let chunk_position = IVec3::new(0, 0, 0);
+
+        let first = generator.generate(&chunk_position, 1, &registry);
+        let second = generator.generate(&chunk_position, 2, &registry);
+
+        assert!((0..CHUNK_VOLUME).any(|block_index| first.get_at(block_index) != second.get_at(block_index)));
+    }
+}
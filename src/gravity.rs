@@ -0,0 +1,228 @@
+use std::collections::HashSet;
+use avian3d::prelude::*;
+use bevy::prelude::*;
+use glam::IVec3;
+
+use crate::registry::*;
+use crate::material::*;
+use crate::lighting::*;
+use crate::region::*;
+use crate::fluid::*;
+use crate::chunk::*;
+use crate::block::*;
+use crate::world::*;
+
+
+const FALLING_BLOCK_SIZE: f32 = 0.98;
+const LANDING_SPEED: f32 = 0.1;
+
+
+// A block that left the voxel grid and falls as a physics body until it lands
+#[derive(Component)]
+pub struct FallingBlock {
+    pub block: Block,
+}
+
+
+// Blocks to check for missing support on the next frame
+#[derive(Resource)]
+pub struct GravityChecks {
+    pub pending: HashSet<IVec3>,
+}
+
+
+impl GravityChecks {
+    /// Starts with no blocks to check.
+    pub fn default() -> Self {
+        Self {
+            pending: HashSet::new(),
+        }
+    }
+
+
+    /// Schedules a changed block and the block resting on it for a support check.
+    pub fn schedule_around(&mut self, block_position: IVec3) {
+        self.pending.insert(block_position);
+        self.pending.insert(block_position + IVec3::Y);
+    }
+
+
+    /// Schedules gravity blocks left without support in a newly loaded chunk, such as sand over a carved cave.
+    pub fn schedule_loaded_chunk(&mut self, world: &WorldChunks, registry: &BlockRegistry, chunk_position: IVec3) {
+        // Blocks at the bottom of the chunk above only find out what is below them once this chunk loads
+        for loaded_chunk in [chunk_position, chunk_position + IVec3::Y] {
+            for block_position in world.find_blocks(loaded_chunk, |block| registry.has_gravity(block)) {
+                let unsupported = world
+                    .get_block(block_position + IVec3::NEG_Y)
+                    .is_some_and(|below| !registry.is_solid(below));
+                if unsupported {
+                    self.pending.insert(block_position);
+                }
+            }
+        }
+    }
+}
+
+
+/// Turns gravity blocks with nothing solid below them into falling physics bodies.
+pub fn drop_unsupported_blocks(
+    registry: Res<BlockRegistry>,
+    chunk_materials: Res<ChunkMaterials>,
+    mut gravity_checks: ResMut<GravityChecks>,
+    mut fluid_ticks: ResMut<FluidTicks>,
+    mut world: ResMut<WorldChunks>,
+    mut chunk_entities: ResMut<ChunkEntities>,
+    mut chunk_tasks: ResMut<ChunkTasks>,
+    mut world_save: ResMut<WorldSave>,
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    let pending: Vec<IVec3> = gravity_checks.pending.drain().collect();
+    let mut changed_chunks = HashSet::new();
+    let mut relit_chunks = HashSet::new();
+
+    for block_position in pending {
+        let Some(block) = world.get_block(block_position) else { continue; };
+        if !registry.has_gravity(block) {
+            continue;
+        }
+
+        // Blocks above unloaded chunks stay put until the ground below them exists
+        let supported = world
+            .get_block(block_position + IVec3::NEG_Y)
+            .is_none_or(|below| registry.is_solid(below));
+        if supported {
+            continue;
+        }
+
+        let Some(chunk_position) = world.set_block(block_position, Block::AIR) else { continue; };
+        world_save.modified.insert(chunk_position);
+        changed_chunks.insert(chunk_position);
+        relit_chunks.extend(update_block_light(&mut world, &registry, block_position));
+
+        // The block resting on this one is checked next frame so columns fall one after another
+        gravity_checks.schedule_around(block_position);
+        fluid_ticks.schedule_around(block_position);
+
+        let block_center = Vec3::new(block_position.x as f32, block_position.y as f32, block_position.z as f32) + Vec3::splat(0.5);
+        let material = if registry.is_translucent(block) { &chunk_materials.translucent } else { &chunk_materials.opaque };
+        commands.spawn((
+            FallingBlock { block },
+            Mesh3d(meshes.add(build_block_mesh(&registry, block))),
+            MeshMaterial3d(material.clone()),
+            Transform::from_translation(block_center),
+            RigidBody::Dynamic,
+            Collider::cuboid(FALLING_BLOCK_SIZE, FALLING_BLOCK_SIZE, FALLING_BLOCK_SIZE),
+            LinearVelocity::default(),
+            LockedAxes::ROTATION_LOCKED,
+            Restitution::new(0.0),
+        ));
+    }
+
+    chunk_tasks.remesh_all(relit_chunks.into_iter().filter(|relit| !changed_chunks.contains(relit)));
+    for chunk_position in changed_chunks {
+        rebuild_chunk(chunk_position, &world, &registry, &chunk_materials, &mut chunk_entities, &mut chunk_tasks, &mut meshes, &mut commands);
+    }
+}
+
+
+/// Gives the chunks falling blocks are in, and the chunks right below them, colliders outside the physics distance.
+pub fn track_falling_blocks(
+    falling_query: Query<&Transform, With<FallingBlock>>,
+    mut physics_chunks: ResMut<PhysicsChunks>,
+) {
+    let chunks: HashSet<IVec3> = falling_query
+        .iter()
+        .flat_map(|transform| {
+            let chunk_position = get_chunk_position(transform.translation);
+            let below_position = get_chunk_position(transform.translation - Vec3::Y);
+            [chunk_position, below_position]
+        })
+        .collect();
+
+    // Only a changed set marks the resource changed and triggers remeshing
+    if physics_chunks.chunks != chunks {
+        physics_chunks.chunks = chunks;
+    }
+}
+
+
+/// Writes blocks still falling when the app exits back into the world at their current position so they are saved.
+pub fn settle_falling_blocks_on_exit(
+    mut exit_events: MessageReader<AppExit>,
+    falling_query: Query<(&FallingBlock, &Transform)>,
+    registry: Res<BlockRegistry>,
+    mut world: ResMut<WorldChunks>,
+    mut world_save: ResMut<WorldSave>,
+) {
+    if exit_events.read().last().is_none() {
+        return;
+    }
+
+    for (falling_block, transform) in falling_query.iter() {
+        let block_position = get_block_position((transform.translation - Vec3::splat(0.5)).round());
+        let Some(target) = world.get_block(block_position) else { continue; };
+
+        // Blocks over the edge of the loaded world or inside something solid are lost as they would be on landing
+        if registry.is_solid(target) {
+            continue;
+        }
+
+        if let Some(chunk_position) = world.set_block(block_position, falling_block.block) {
+            world_save.modified.insert(chunk_position);
+        }
+    }
+}
+
+
+/// Places falling blocks back into the world once they come to rest on a solid block.
+pub fn land_falling_blocks(
+    falling_query: Query<(Entity, &FallingBlock, &Transform, &LinearVelocity)>,
+    registry: Res<BlockRegistry>,
+    chunk_materials: Res<ChunkMaterials>,
+    mut gravity_checks: ResMut<GravityChecks>,
+    mut fluid_ticks: ResMut<FluidTicks>,
+    mut world: ResMut<WorldChunks>,
+    mut chunk_entities: ResMut<ChunkEntities>,
+    mut chunk_tasks: ResMut<ChunkTasks>,
+    mut world_save: ResMut<WorldSave>,
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    for (entity, falling_block, transform, linear_velocity) in falling_query.iter() {
+        let block_position = get_block_position((transform.translation - Vec3::splat(0.5)).round());
+
+        // Blocks that fall out of the loaded world are dropped
+        let Some(target) = world.get_block(block_position) else {
+            commands.entity(entity).despawn();
+            continue;
+        };
+
+        if linear_velocity.y.abs() > LANDING_SPEED {
+            continue;
+        }
+
+        let landed = world
+            .get_block(block_position + IVec3::NEG_Y)
+            .is_some_and(|below| registry.is_solid(below));
+        if !landed {
+            continue;
+        }
+
+        commands.entity(entity).despawn();
+
+        // Blocks that come to rest inside something solid are crushed
+        if registry.is_solid(target) {
+            continue;
+        }
+
+        if let Some(chunk_position) = world.set_block(block_position, falling_block.block) {
+            world_save.modified.insert(chunk_position);
+            fluid_ticks.schedule_around(block_position);
+            gravity_checks.schedule_around(block_position);
+            let relit_chunks = update_block_light(&mut world, &registry, block_position);
+            chunk_tasks.remesh_all(relit_chunks.into_iter().filter(|relit| *relit != chunk_position));
+            rebuild_chunk(chunk_position, &world, &registry, &chunk_materials, &mut chunk_entities, &mut chunk_tasks, &mut meshes, &mut commands);
+        }
+    }
+}
@@ -0,0 +1,204 @@
+use std::path::Path;
+use glam::IVec3;
+
+use crate::generator::*;
+use crate::structure::*;
+use crate::registry::*;
+use crate::biome::*;
+use crate::chunk::*;
+use crate::block::*;
+use crate::world::*;
+
+
+// Height range pixel intensities map onto unless the generator options say otherwise
+const DEFAULT_MIN_HEIGHT: i32 = 0;
+const DEFAULT_MAX_HEIGHT: i32 = 64;
+
+
+// How columns outside the image are sampled
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum HeightmapEdge {
+    Clamp,
+    Tile,
+}
+
+
+// A grayscale image, 8 or 16 bit, stored as intensities from 0 to 1
+struct HeightImage {
+    width: u32,
+    depth: u32,
+    intensities: Vec<f32>,
+}
+
+
+// A color image with every pixel resolved to a biome
+struct BiomeImage {
+    width: u32,
+    depth: u32,
+    biomes: Vec<Biome>,
+}
+
+
+// Terrain read from a heightmap image centred on the world origin, with an optional biome map image
+pub struct HeightmapGenerator {
+    heights: HeightImage,
+    biomes: Option<BiomeImage>,
+    edge: HeightmapEdge,
+    min_height: i32,
+    max_height: i32,
+    terrain_blocks: TerrainBlocks,
+    save_name: String,
+}
+
+
+impl HeightmapEdge {
+    /// Returns the pixel a world coordinate samples along an image axis of the given size.
+    fn pixel(self, world_coordinate: i32, size: u32) -> u32 {
+        let pixel = world_coordinate + size as i32 / 2;
+        match self {
+            HeightmapEdge::Clamp => pixel.clamp(0, size as i32 - 1) as u32,
+            HeightmapEdge::Tile => pixel.rem_euclid(size as i32) as u32,
+        }
+    }
+}
+
+
+impl HeightImage {
+    /// Reads an image as 16 bit grayscale so both 8 and 16 bit heightmaps keep their full precision.
+    fn load(path: &Path) -> Result<Self, String> {
+        let image = image::open(path)
+            .map_err(|error| format!("could not read heightmap {}: {error}", path.display()))?
+            .into_luma16();
+        if image.width() == 0 || image.height() == 0 {
+            return Err(format!("heightmap {} has no pixels", path.display()));
+        }
+
+        Ok(Self {
+            width: image.width(),
+            depth: image.height(),
+            intensities: image.pixels().map(|pixel| pixel.0[0] as f32 / u16::MAX as f32).collect(),
+        })
+    }
+}
+
+
+impl BiomeImage {
+    /// Reads a color image and resolves every pixel to the biome with the closest map color.
+    fn load(path: &Path) -> Result<Self, String> {
+        let image = image::open(path)
+            .map_err(|error| format!("could not read biome map {}: {error}", path.display()))?
+            .into_rgb8();
+        if image.width() == 0 || image.height() == 0 {
+            return Err(format!("biome map {} has no pixels", path.display()));
+        }
+
+        Ok(Self {
+            width: image.width(),
+            depth: image.height(),
+            biomes: image.pixels().map(|pixel| Biome::from_map_color(pixel.0)).collect(),
+        })
+    }
+}
+
+
+impl HeightmapGenerator {
+    /// Parses heightmap options such as maps/island.png,biomes=maps/island_biomes.png,edge=tile,min=0,max=96.
+    pub fn from_options(options: &str, registry: &BlockRegistry) -> Result<Self, String> {
+        let options_text = options;
+        let mut options = options.split(',').map(str::trim);
+        let height_path = options.next().filter(|path| !path.is_empty())
+            .ok_or("heightmap generator needs an image path")?;
+
+        let mut generator = Self {
+            heights: HeightImage::load(Path::new(height_path))?,
+            biomes: None,
+            edge: HeightmapEdge::Clamp,
+            min_height: DEFAULT_MIN_HEIGHT,
+            max_height: DEFAULT_MAX_HEIGHT,
+            terrain_blocks: TerrainBlocks::resolve(registry)?,
+            save_name: heightmap_save_name(height_path, options_text),
+        };
+
+        for option in options {
+            let (key, value) = option.split_once('=')
+                .ok_or_else(|| format!("heightmap option {option} is not key=value"))?;
+            let parse_height = |value: &str| {
+                value.parse::<i32>().map_err(|_| format!("invalid heightmap {key} height {value}"))
+            };
+
+            match key {
+                "biomes" => generator.biomes = Some(BiomeImage::load(Path::new(value))?),
+                "edge" if value == "clamp" => generator.edge = HeightmapEdge::Clamp,
+                "edge" if value == "tile" => generator.edge = HeightmapEdge::Tile,
+                "min" => generator.min_height = parse_height(value)?,
+                "max" => generator.max_height = parse_height(value)?,
+                _ => return Err(format!("unknown heightmap option {option}")),
+            }
+        }
+
+        Ok(generator)
+    }
+
+
+    /// Returns the biome and surface height the images give a column.
+    fn column_at(&self, world_x: i32, world_z: i32) -> BiomeColumn {
+        let pixel_x = self.edge.pixel(world_x, self.heights.width);
+        let pixel_z = self.edge.pixel(world_z, self.heights.depth);
+        let intensity = self.heights.intensities[(pixel_z * self.heights.width + pixel_x) as usize];
+        let height_range = (self.max_height - self.min_height) as f32;
+
+        let biome = match &self.biomes {
+            Some(biome_image) => {
+                let pixel_x = self.edge.pixel(world_x, biome_image.width);
+                let pixel_z = self.edge.pixel(world_z, biome_image.depth);
+                biome_image.biomes[(pixel_z * biome_image.width + pixel_x) as usize]
+            }
+            None => Biome::Plains,
+        };
+
+        BiomeColumn {
+            biome,
+            height: self.min_height + (intensity * height_range).round() as i32,
+        }
+    }
+}
+
+
+impl WorldGenerator for HeightmapGenerator {
+    fn generate(&self, chunk_position: &IVec3, seed: u32, _registry: &BlockRegistry) -> Chunk {
+        let column_at = |world_x: i32, world_z: i32| self.column_at(world_x, world_z);
+        let (mut chunk, _surface_heights) = fill_columns(chunk_position, seed, &self.terrain_blocks, &column_at);
+
+        place_structures(&mut chunk, chunk_position, seed, &self.terrain_blocks.structures, column_at, None);
+
+        chunk.compact();
+        chunk
+    }
+
+
+    fn surface_at(&self, world_x: i32, world_z: i32, _seed: u32, _registry: &BlockRegistry) -> Option<(i32, Block)> {
+        let column = self.column_at(world_x, world_z);
+        Some((column.height, self.terrain_blocks.surface(column.biome)))
+    }
+
+
+    fn biome_at(&self, world_x: i32, world_z: i32, _seed: u32) -> Option<Biome> {
+        Some(self.column_at(world_x, world_z).biome)
+    }
+
+
+    fn save_name(&self) -> Option<&str> {
+        Some(&self.save_name)
+    }
+}
+
+
+/// Names saves after the heightmap image, with a hash of every option so each image and option set gets its own saves.
+fn heightmap_save_name(height_path: &str, options: &str) -> String {
+    let image_name = Path::new(height_path)
+        .file_stem()
+        .map(|stem| sanitize_save_name(&stem.to_string_lossy()))
+        .unwrap_or_default();
+
+    format!("heightmap_{image_name}_{:08x}", hash_text(options))
+}
@@ -0,0 +1,403 @@
+use std::collections::VecDeque;
+use std::collections::HashSet;
+use glam::IVec3;
+
+use crate::registry::*;
+use crate::block::*;
+use crate::chunk::*;
+use crate::world::*;
+
+
+pub const MAX_LIGHT: u8 = 15;
+
+
+// Light channels packed four bits each into a voxels light value
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum LightChannel {
+    Sky,
+    Red,
+    Green,
+    Blue,
+}
+
+
+// Channels carrying light emitted by blocks
+const BLOCK_LIGHT_CHANNELS: [LightChannel; 3] = [LightChannel::Red, LightChannel::Green, LightChannel::Blue];
+
+
+// Light levels of every voxel in a chunk, one nibble section per channel
+#[derive(Clone)]
+pub struct LightStorage {
+    channels: [NibbleSection; 4],
+}
+
+
+// Levels of one light channel, kept as a single level while the channel is even
+// and packed two voxels per byte once it varies
+#[derive(Clone)]
+struct NibbleSection {
+    uniform: u8,
+    nibbles: Vec<u8>,
+}
+
+
+// Pending light updates and the chunks they changed
+#[derive(Default)]
+struct LightQueues {
+    add: VecDeque<IVec3>,
+    remove: VecDeque<(IVec3, u8)>,
+    changed: HashSet<IVec3>,
+}
+
+
+impl LightChannel {
+    pub const ALL: [LightChannel; 4] = [LightChannel::Sky, LightChannel::Red, LightChannel::Green, LightChannel::Blue];
+
+
+    /// Returns the bit offset of the channel inside a light value.
+    fn shift(self) -> u16 {
+        self as u16 * 4
+    }
+}
+
+
+impl LightStorage {
+    /// Creates storage where every voxel has the given light value.
+    pub fn filled(value: u16) -> Self {
+        Self {
+            channels: LightChannel::ALL.map(|channel| NibbleSection::filled(((value >> channel.shift()) & 0xF) as u8)),
+        }
+    }
+
+
+    /// Returns the level of a channel at the given block index.
+    pub fn get(&self, block_index: usize, channel: LightChannel) -> u8 {
+        self.channels[channel as usize].get(block_index)
+    }
+
+
+    /// Sets the level of a channel at the given block index.
+    pub fn set(&mut self, block_index: usize, channel: LightChannel, level: u8) {
+        self.channels[channel as usize].set(block_index, level & 0xF);
+    }
+
+
+    /// Sets a channel to the same level in every voxel.
+    pub fn fill(&mut self, channel: LightChannel, level: u8) {
+        self.channels[channel as usize] = NibbleSection::filled(level & 0xF);
+    }
+
+
+    /// Collapses every evenly lit channel back to a single level.
+    pub fn compact(&mut self) {
+        for section in &mut self.channels {
+            section.compact();
+        }
+    }
+}
+
+
+impl NibbleSection {
+    fn filled(level: u8) -> Self {
+        Self {
+            uniform: level,
+            nibbles: Vec::new(),
+        }
+    }
+
+
+    fn get(&self, block_index: usize) -> u8 {
+        if self.nibbles.is_empty() {
+            return self.uniform;
+        }
+
+        (self.nibbles[block_index / 2] >> (block_index % 2 * 4)) & 0xF
+    }
+
+
+    fn set(&mut self, block_index: usize, level: u8) {
+        if self.get(block_index) == level {
+            return;
+        }
+
+        if self.nibbles.is_empty() {
+            self.nibbles = vec![self.uniform | (self.uniform << 4); CHUNK_VOLUME / 2];
+        }
+
+        let shift = block_index % 2 * 4;
+        let byte = &mut self.nibbles[block_index / 2];
+        *byte = (*byte & !(0xF << shift)) | (level << shift);
+    }
+
+
+    fn compact(&mut self) {
+        let Some(first) = self.nibbles.first().copied() else { return; };
+        if first & 0xF == first >> 4 && self.nibbles.iter().all(|byte| *byte == first) {
+            *self = Self::filled(first & 0xF);
+        }
+    }
+}
+
+
+impl LightQueues {
+    /// Records the chunk containing a changed voxel and every chunk whose meshes sample it.
+    fn mark_changed(&mut self, block_position: IVec3) {
+        self.changed.extend(chunks_sampling_block(block_position));
+    }
+
+
+    /// Clears a voxels light and queues the light it spread for removal.
+    fn remove(&mut self, world: &mut WorldChunks, block_position: IVec3, channel: LightChannel) {
+        let Some(level) = world.get_light(block_position, channel) else { return; };
+        if level == 0 {
+            return;
+        }
+
+        world.set_light(block_position, channel, 0);
+        self.remove.push_back((block_position, level));
+        self.mark_changed(block_position);
+    }
+}
+
+
+/// Returns the level light reaches after spreading one block in the given direction.
+fn spread_level(channel: LightChannel, level: u8, direction: IVec3) -> u8 {
+    // Full sunlight travels straight down without fading
+    if channel == LightChannel::Sky && level == MAX_LIGHT && direction == IVec3::NEG_Y {
+        return MAX_LIGHT;
+    }
+
+    level.saturating_sub(1)
+}
+
+
+/// Removes light that was spread from queued voxels and queues the light around it to refill the gap.
+fn propagate_removal(world: &mut WorldChunks, registry: &BlockRegistry, channel: LightChannel, queues: &mut LightQueues) {
+    while let Some((block_position, level)) = queues.remove.pop_front() {
+        for direction in CHUNK_NEIGHBOURS {
+            let neighbor_position = block_position + direction;
+            let Some(neighbor_level) = world.get_light(neighbor_position, channel) else { continue; };
+            if neighbor_level == 0 {
+                continue;
+            }
+
+            // Light this voxel handed on is removed, brighter light from elsewhere spreads back in
+            if neighbor_level < level || neighbor_level == spread_level(channel, level, direction) {
+                world.set_light(neighbor_position, channel, 0);
+                queues.remove.push_back((neighbor_position, neighbor_level));
+                queues.mark_changed(neighbor_position);
+
+                // Emitters caught in the removal relight themselves
+                let emitted = world.get_block(neighbor_position).map_or(0, |block| emitted_level(registry, block, channel));
+                if emitted > 0 {
+                    world.set_light(neighbor_position, channel, emitted);
+                    queues.add.push_back(neighbor_position);
+                }
+            }
+            else {
+                queues.add.push_back(neighbor_position);
+            }
+        }
+    }
+}
+
+
+/// Spreads light from queued voxels into every loaded voxel that does not block it.
+fn propagate_addition(world: &mut WorldChunks, registry: &BlockRegistry, channel: LightChannel, queues: &mut LightQueues) {
+    while let Some(block_position) = queues.add.pop_front() {
+        let Some(level) = world.get_light(block_position, channel) else { continue; };
+
+        for direction in CHUNK_NEIGHBOURS {
+            let neighbor_level = spread_level(channel, level, direction);
+            if neighbor_level == 0 {
+                continue;
+            }
+
+            let neighbor_position = block_position + direction;
+            match world.get_block(neighbor_position) {
+                Some(block) if !registry.is_opaque(block) => {}
+                _ => continue,
+            }
+
+            if world.get_light(neighbor_position, channel).is_some_and(|current| current < neighbor_level) {
+                world.set_light(neighbor_position, channel, neighbor_level);
+                queues.add.push_back(neighbor_position);
+                queues.mark_changed(neighbor_position);
+            }
+        }
+    }
+}
+
+
+/// Runs queued removals then additions and returns the loaded chunks whose meshes need rebuilding.
+fn propagate(world: &mut WorldChunks, registry: &BlockRegistry, channel: LightChannel, mut queues: LightQueues) -> HashSet<IVec3> {
+    propagate_removal(world, registry, channel, &mut queues);
+    propagate_addition(world, registry, channel, &mut queues);
+
+    queues.changed.retain(|chunk_position| world.chunks.contains_key(chunk_position));
+    queues.changed
+}
+
+
+/// Returns the world position of a chunks first block.
+fn chunk_origin(chunk_position: IVec3) -> IVec3 {
+    chunk_position * IVec3::new(CHUNK_SIZE_X as i32, CHUNK_SIZE_Y as i32, CHUNK_SIZE_Z as i32)
+}
+
+
+/// Returns the world positions of the blocks on the side of a chunk facing the given direction.
+fn face_blocks(chunk_position: IVec3, direction: IVec3) -> Vec<IVec3> {
+    let origin = chunk_origin(chunk_position);
+    let chunk_size = IVec3::new(CHUNK_SIZE_X as i32, CHUNK_SIZE_Y as i32, CHUNK_SIZE_Z as i32);
+    let mut blocks = Vec::new();
+
+    for block_y in 0..chunk_size.y {
+        for block_z in 0..chunk_size.z {
+            for block_x in 0..chunk_size.x {
+                let local = IVec3::new(block_x, block_y, block_z);
+                let on_face = (0..3).any(|axis| {
+                    (direction[axis] < 0 && local[axis] == 0)
+                        || (direction[axis] > 0 && local[axis] == chunk_size[axis] - 1)
+                });
+
+                if on_face {
+                    blocks.push(origin + local);
+                }
+            }
+        }
+    }
+
+    blocks
+}
+
+
+/// Returns the level a block emits on a light channel.
+fn emitted_level(registry: &BlockRegistry, block: Block, channel: LightChannel) -> u8 {
+    let emission = registry.get(block).light_emission;
+    match channel {
+        LightChannel::Sky => 0,
+        LightChannel::Red => emission[0],
+        LightChannel::Green => emission[1],
+        LightChannel::Blue => emission[2],
+    }
+}
+
+
+/// Computes sky and block light for a freshly loaded chunk on its own, as if open sky was above it.
+pub fn light_new_chunk(chunk_position: IVec3, chunk: Chunk, registry: &BlockRegistry) -> Chunk {
+    let mut chunk = chunk;
+    if chunk.is_empty() {
+        chunk.fill_light(LightChannel::Sky, MAX_LIGHT);
+        return chunk;
+    }
+
+    let mut scratch = WorldChunks::default();
+    scratch.chunks.insert(chunk_position, chunk);
+
+    // Sunlight enters through the top layer and falls down each column during propagation
+    let mut queues = LightQueues::default();
+    for block_position in face_blocks(chunk_position, IVec3::Y) {
+        if scratch.get_block(block_position).is_some_and(|block| !registry.is_opaque(block)) {
+            scratch.set_light(block_position, LightChannel::Sky, MAX_LIGHT);
+            queues.add.push_back(block_position);
+        }
+    }
+    propagate(&mut scratch, registry, LightChannel::Sky, queues);
+
+    // Block light starts at every emitting block
+    let origin = chunk_origin(chunk_position);
+    for channel in BLOCK_LIGHT_CHANNELS {
+        let mut queues = LightQueues::default();
+        for (block_index, (block_x, block_y, block_z)) in GET_COORDS.iter().enumerate() {
+            let block = scratch.chunks[&chunk_position].get_at(block_index);
+            let level = emitted_level(registry, block, channel);
+            if level > 0 {
+                let block_position = origin + IVec3::new(*block_x as i32, *block_y as i32, *block_z as i32);
+                scratch.set_light(block_position, channel, level);
+                queues.add.push_back(block_position);
+            }
+        }
+        propagate(&mut scratch, registry, channel, queues);
+    }
+
+    let mut chunk = scratch.chunks.remove(&chunk_position).unwrap();
+    chunk.compact_light();
+    chunk
+}
+
+
+/// Exchanges light between a newly inserted chunk and its loaded neighbours, returning the chunks that changed.
+pub fn reconcile_chunk_light(world: &mut WorldChunks, registry: &BlockRegistry, chunk_position: IVec3) -> HashSet<IVec3> {
+    let borders: Vec<(IVec3, Vec<IVec3>)> = CHUNK_NEIGHBOURS
+        .into_iter()
+        .filter(|direction| world.chunks.contains_key(&(chunk_position + *direction)))
+        .map(|direction| (direction, face_blocks(chunk_position, direction)))
+        .collect();
+
+    let mut changed = HashSet::new();
+    for channel in LightChannel::ALL {
+        let mut queues = LightQueues::default();
+
+        for (direction, blocks) in &borders {
+            for block_position in blocks {
+                let neighbor_position = *block_position + *direction;
+
+                // Chunks lit as if under open sky lose that sunlight when the chunk above shades them
+                if channel == LightChannel::Sky {
+                    let level = world.get_light(*block_position, channel).unwrap_or(0);
+                    let neighbor_level = world.get_light(neighbor_position, channel).unwrap_or(0);
+
+                    if *direction == IVec3::Y && level == MAX_LIGHT && neighbor_level < MAX_LIGHT {
+                        queues.remove(world, *block_position, channel);
+                    }
+                    if *direction == IVec3::NEG_Y && neighbor_level == MAX_LIGHT && level < MAX_LIGHT {
+                        queues.remove(world, neighbor_position, channel);
+                    }
+                }
+
+                queues.add.push_back(*block_position);
+                queues.add.push_back(neighbor_position);
+            }
+        }
+
+        changed.extend(propagate(world, registry, channel, queues));
+    }
+
+    changed
+}
+
+
+/// Updates light around a block that was just placed or removed, returning the chunks that changed.
+pub fn update_block_light(world: &mut WorldChunks, registry: &BlockRegistry, block_position: IVec3) -> HashSet<IVec3> {
+    let Some(block) = world.get_block(block_position) else { return HashSet::new(); };
+    let mut changed = HashSet::new();
+
+    for channel in LightChannel::ALL {
+        let mut queues = LightQueues::default();
+
+        // Light that passed through or came from the old block is cleared before refilling the space
+        queues.remove(world, block_position, channel);
+
+        // Blocks under an unloaded chunk are treated as open to the sky, as when the chunk was lit
+        let open_sky = channel == LightChannel::Sky
+            && !registry.is_opaque(block)
+            && world.get_light(block_position + IVec3::Y, channel).is_none();
+
+        let level = if open_sky { MAX_LIGHT } else { emitted_level(registry, block, channel) };
+        if level > 0 {
+            world.set_light(block_position, channel, level);
+            queues.add.push_back(block_position);
+            queues.mark_changed(block_position);
+        }
+
+        if !registry.is_opaque(block) {
+            for direction in CHUNK_NEIGHBOURS {
+                queues.add.push_back(block_position + direction);
+            }
+        }
+
+        changed.extend(propagate(world, registry, channel, queues));
+    }
+
+    changed
+}
@@ -1,3 +1,6 @@
+// Bevy systems take one parameter per resource and query they use
+#![allow(clippy::too_many_arguments)]
+
 use bevy::pbr::wireframe::WireframePlugin;
 use bevy::render::RenderDebugFlags;
 use bevy::window::PresentMode;
@@ -11,10 +14,25 @@ mod crosshair;
 mod settings;
 mod player;
 mod chunk;
+mod palette;
+mod region;
+mod raycast;
 mod world;
 mod block;
 mod light;
 mod overlay;
+mod material;
+mod registry;
+mod lighting;
+mod fluid;
+mod gravity;
+mod cave;
+mod biome;
+mod structure;
+mod generator;
+mod terrain;
+mod heightmap;
+mod far_terrain;
 
 
 fn main() {
@@ -34,6 +52,7 @@ fn main() {
         debug_flags: RenderDebugFlags::empty(),
     });
     game.add_plugins(PhysicsPlugins::default());
+    game.add_plugins(MaterialPlugin::<material::ChunkMaterial>::default());
     game.add_plugins(overlay::StatsOverlayPlugin);
 
     // --- Observers
@@ -43,15 +62,37 @@ fn main() {
     game.insert_resource(world::WorldChunks::default());
     game.insert_resource(world::ChunkEntities::default());
     game.insert_resource(world::ChunkQueue::default());
+    game.insert_resource(world::ChunkTasks::default());
+    game.insert_resource(world::PhysicsChunks::default());
+    game.insert_resource(world::ChunkLoadSettings::default());
+    game.insert_resource(world::WorldBounds::default());
+    game.insert_resource(fluid::FluidTicks::default());
+    game.insert_resource(gravity::GravityChecks::default());
+    game.insert_resource(far_terrain::FarTerrain::default());
+    let block_registry = registry::BlockRegistry::load(registry::resolve_game_path(registry::BLOCK_REGISTRY_PATH))
+        .unwrap_or_else(|error| panic!("Failed to load block registry: {error}"));
+    let terrain_settings = terrain::TerrainNoiseSettings::load(registry::resolve_game_path(terrain::TERRAIN_SETTINGS_PATH))
+        .unwrap_or_else(|error| panic!("Failed to load terrain settings: {error}"));
+    let chunk_generator = generator::ChunkGenerator::from_args(&block_registry, &terrain_settings)
+        .unwrap_or_else(|error| panic!("Failed to create world generator: {error}"));
+    let world_seed = world::WorldSeed::from_args();
+    game.insert_resource(region::WorldSave::for_world(&world_seed, &chunk_generator));
+    game.insert_resource(block_registry);
+    game.insert_resource(chunk_generator);
+    game.insert_resource(terrain_settings);
+    game.insert_resource(world_seed);
     game.insert_resource(world::PlayerChunk { last_chunk: glam::IVec3::new(i32::MIN, 0, i32::MIN) });
     game.insert_resource(player::PlayerSettings::default());
     game.insert_resource(player::JumpState::default());
     game.insert_resource(player::BlockActions::default());
+    game.insert_resource(raycast::TargetedBlock::default());
     game.insert_resource(ClearColor(Color::srgb(0.392, 0.584, 0.929)));
 
     // --- Load systems on startup
     game.add_systems(Startup, light::setup_lighting);
+    game.add_systems(Startup, material::setup_chunk_material);
     game.add_systems(Startup, crosshair::setup_crosshair);
+    game.add_systems(Startup, far_terrain::setup_far_terrain);
     game.add_systems(Startup, player::spawn_player); 
 
     // --- Load systems on update frame
@@ -61,9 +102,28 @@ fn main() {
     game.add_systems(Update, player::player_movement);
     // Queue, load and unload chunks around the players position
     game.add_systems(Update, world::queue_chunks.after(player::player_movement));
-    game.add_systems(Update, world::load_chunks.after(world::queue_chunks));
-    game.add_systems(Update, world::unload_chunks.after(world::load_chunks));
-    game.add_systems(Update, player::destroy_block);
+    game.add_systems(Update, world::update_chunk_details.after(world::queue_chunks));
+    game.add_systems(Update, generator::apply_terrain_settings.before(world::load_chunks));
+    game.add_systems(Update, world::load_chunks.after(world::update_chunk_details));
+    game.add_systems(Update, world::poll_chunk_tasks.after(world::load_chunks));
+    game.add_systems(Update, world::spawn_chunks.after(world::poll_chunk_tasks));
+    game.add_systems(Update, world::unload_chunks.after(world::spawn_chunks));
+    // Fill the distance past the loaded chunks with low poly terrain
+    game.add_systems(Update, far_terrain::update_far_terrain.after(world::unload_chunks));
+    game.add_systems(Update, raycast::update_targeted_block.after(player::camera_look).after(player::player_movement));
+    game.add_systems(Update, player::destroy_block.after(raycast::update_targeted_block));
+    game.add_systems(Update, player::select_block);
+    game.add_systems(Update, player::place_block.after(player::destroy_block).after(player::select_block));
+    game.add_systems(Update, fluid::tick_fluids.after(player::place_block));
+    // Drop unsupported gravity blocks and put them back into the world once they land
+    game.add_systems(Update, gravity::drop_unsupported_blocks.after(player::place_block));
+    game.add_systems(Update, gravity::land_falling_blocks.after(gravity::drop_unsupported_blocks));
+    game.add_systems(Update, gravity::track_falling_blocks.after(gravity::land_falling_blocks));
+    // Keep translucent faces ordered back to front after chunks or the camera change
+    game.add_systems(Update, world::sort_translucent_faces.after(world::unload_chunks).after(fluid::tick_fluids));
+    // Write edited chunks to disk once the app is closing
+    game.add_systems(Last, gravity::settle_falling_blocks_on_exit.before(region::save_on_exit));
+    game.add_systems(Last, region::save_on_exit);
     
     game.run();
 }
\ No newline at end of file
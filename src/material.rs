@@ -0,0 +1,72 @@
+use bevy::pbr::{ExtendedMaterial, MaterialExtension};
+use bevy::render::render_resource::AsBindGroup;
+use bevy::shader::ShaderRef;
+use bevy::prelude::*;
+
+use crate::block::*;
+
+
+const SHADER_ASSET_PATH: &str = "shaders/chunk.wgsl";
+const PREPASS_SHADER_ASSET_PATH: &str = "shaders/chunk_prepass.wgsl";
+
+
+// Standard material whose uvs repeat within a texture atlas tile
+pub type ChunkMaterial = ExtendedMaterial<StandardMaterial, AtlasExtension>;
+
+
+// Atlas tiling data passed to the chunk shader
+#[derive(Asset, AsBindGroup, Reflect, Debug, Clone, Default)]
+pub struct AtlasExtension {
+    #[uniform(100)]
+    pub tile_size: f32,
+}
+
+
+impl MaterialExtension for AtlasExtension {
+    fn fragment_shader() -> ShaderRef {
+        SHADER_ASSET_PATH.into()
+    }
+
+
+    // Depth and shadow passes alpha test the same atlas texels as the main pass
+    fn prepass_fragment_shader() -> ShaderRef {
+        PREPASS_SHADER_ASSET_PATH.into()
+    }
+}
+
+
+// Shared material handles used by every chunk
+#[derive(Resource)]
+pub struct ChunkMaterials {
+    pub opaque: Handle<ChunkMaterial>,
+    pub translucent: Handle<ChunkMaterial>,
+}
+
+
+/// Creates the opaque and translucent chunk materials from the texture atlas.
+pub fn setup_chunk_material(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<ChunkMaterial>>,
+    asset_server: Res<AssetServer>,
+) {
+    let texture_handle = asset_server.load("texture_atlas.png");
+    let mut chunk_material = |alpha_mode| {
+        materials.add(ExtendedMaterial {
+            base: StandardMaterial {
+                base_color_texture: Some(texture_handle.clone()),
+                perceptual_roughness: 0.2,
+                alpha_mode,
+                ..Default::default()
+            },
+            extension: AtlasExtension {
+                tile_size: TILE_SIZE,
+            },
+        })
+    };
+
+    // Transparent blocks such as glass cut out their clear pixels in the opaque pass
+    let opaque = chunk_material(AlphaMode::Mask(0.5));
+    let translucent = chunk_material(AlphaMode::Blend);
+
+    commands.insert_resource(ChunkMaterials { opaque, translucent });
+}
@@ -1,5 +1,9 @@
 use bevy::prelude::*;
 use bevy::sprite::Anchor;
+use crate::raycast::TargetedBlock;
+use crate::generator::ChunkGenerator;
+use crate::world::get_block_position;
+use crate::world::WorldSeed;
 use crate::player::Player;
 
 
@@ -24,13 +28,24 @@ fn stats_overlay(
     player_query: Query<&GlobalTransform, With<Player>>,
     mut text_query: Query<(&mut Text2d, &mut Transform)>,
     mut text_overlay: ResMut<TextOverlay>,
+    targeted_block: Res<TargetedBlock>,
+    chunk_generator: Res<ChunkGenerator>,
+    world_seed: Res<WorldSeed>,
     window_query: Query<&Window>,
 ) {
     let player_transform = if let Ok(transform) = player_query.single() { transform }
     else { return; };
 
     let position = player_transform.translation();
-    let player_position = format!("XYZ: {:.1} {:.1} {:.1}", position.x, position.y, position.z);
+    let mut player_position = format!("XYZ: {:.1} {:.1} {:.1}", position.x, position.y, position.z);
+    let block_position = get_block_position(position);
+    if let Some(biome) = chunk_generator.0.biome_at(block_position.x, block_position.z, world_seed.0) {
+        player_position.push_str(&format!("\nBiome: {}", biome.profile().name));
+    }
+    if let Some(hit) = targeted_block.0 {
+        let block = hit.block_position;
+        player_position.push_str(&format!("\nBlock: {} {} {} ({:.1} away)", block.x, block.y, block.z, hit.distance));
+    }
     let window = window_query.single().expect("No primary window found");
 
     let text_position = Vec3::new(
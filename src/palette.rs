@@ -0,0 +1,279 @@
+use crate::chunk::CHUNK_VOLUME;
+use crate::block::*;
+
+
+const WORD_BITS: usize = u64::BITS as usize;
+
+
+// Palette compressed block storage for a single chunk
+#[derive(Clone)]
+pub struct PaletteStorage {
+    palette: Vec<Block>,
+    bits_per_index: usize,
+    data: Vec<u64>,
+}
+
+
+impl PaletteStorage {
+    /// Creates storage where every block is the given block.
+    pub fn filled(block: Block) -> Self {
+        Self {
+            palette: vec![block],
+            bits_per_index: 0,
+            data: Vec::new(),
+        }
+    }
+
+
+    /// Returns the block stored at the given index.
+    pub fn get(&self, block_index: usize) -> Block {
+        if self.bits_per_index == 0 {
+            return self.palette[0];
+        }
+
+        self.palette[self.read_index(block_index)]
+    }
+
+
+    /// Stores a block at the given index, growing the palette when needed.
+    pub fn set(&mut self, block_index: usize, block: Block) {
+        if self.get(block_index) == block {
+            return;
+        }
+
+        let palette_index = match self.palette.iter().position(|entry| *entry == block) {
+            Some(palette_index) => palette_index,
+            None => {
+                self.palette.push(block);
+                if self.palette.len() > 1 << self.bits_per_index {
+                    self.repack(bits_for(self.palette.len()));
+                }
+                self.palette.len() - 1
+            }
+        };
+
+        self.write_index(block_index, palette_index);
+    }
+
+
+    /// Rebuilds storage from its raw parts, returning None when they are inconsistent.
+    pub fn from_raw(palette: Vec<Block>, bits_per_index: usize, data: Vec<u64>) -> Option<Self> {
+        if palette.is_empty() || bits_per_index >= WORD_BITS || palette.len() > 1 << bits_per_index {
+            return None;
+        }
+
+        let expected_words = WORD_BITS.checked_div(bits_per_index).map_or(0, |per_word| CHUNK_VOLUME.div_ceil(per_word));
+        if data.len() != expected_words {
+            return None;
+        }
+
+        let storage = Self { palette, bits_per_index, data };
+        if bits_per_index > 0 && (0..CHUNK_VOLUME).any(|block_index| storage.read_index(block_index) >= storage.palette.len()) {
+            return None;
+        }
+
+        Some(storage)
+    }
+
+
+    /// Returns the palette, index width and packed indices backing the storage.
+    pub fn raw(&self) -> (&[Block], usize, &[u64]) {
+        (&self.palette, self.bits_per_index, &self.data)
+    }
+
+
+    /// Returns the block filling the whole chunk when the storage is uniform.
+    pub fn uniform(&self) -> Option<Block> {
+        if self.bits_per_index == 0 { Some(self.palette[0]) } else { None }
+    }
+
+
+    /// Drops unused palette entries and shrinks the index width to fit.
+    pub fn compact(&mut self) {
+        if self.bits_per_index == 0 {
+            return;
+        }
+
+        let mut used = vec![false; self.palette.len()];
+        for block_index in 0..CHUNK_VOLUME {
+            used[self.read_index(block_index)] = true;
+        }
+
+        if used.iter().all(|used| *used) {
+            return;
+        }
+
+        let mut compacted = PaletteStorage::filled(self.get(0));
+        for block_index in 1..CHUNK_VOLUME {
+            compacted.set(block_index, self.get(block_index));
+        }
+
+        *self = compacted;
+    }
+
+
+    fn read_index(&self, block_index: usize) -> usize {
+        let per_word = WORD_BITS / self.bits_per_index;
+        let word = self.data[block_index / per_word];
+        let shift = (block_index % per_word) * self.bits_per_index;
+        let mask = (1u64 << self.bits_per_index) - 1;
+
+        ((word >> shift) & mask) as usize
+    }
+
+
+    fn write_index(&mut self, block_index: usize, palette_index: usize) {
+        let per_word = WORD_BITS / self.bits_per_index;
+        let shift = (block_index % per_word) * self.bits_per_index;
+        let mask = (1u64 << self.bits_per_index) - 1;
+        let word = &mut self.data[block_index / per_word];
+
+        *word = (*word & !(mask << shift)) | ((palette_index as u64 & mask) << shift);
+    }
+
+
+    /// Re-encodes every index with a new bit width.
+    fn repack(&mut self, bits_per_index: usize) {
+        let previous = std::mem::replace(self, Self {
+            palette: Vec::new(),
+            bits_per_index,
+            data: vec![0; CHUNK_VOLUME.div_ceil(WORD_BITS / bits_per_index)],
+        });
+
+        for block_index in 0..CHUNK_VOLUME {
+            let palette_index = if previous.bits_per_index == 0 { 0 } else { previous.read_index(block_index) };
+            self.write_index(block_index, palette_index);
+        }
+
+        self.palette = previous.palette;
+    }
+}
+
+
+/// Returns the number of bits needed to index a palette of the given length.
+fn bits_for(palette_length: usize) -> usize {
+    (usize::BITS - (palette_length - 1).leading_zeros()) as usize
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    /// Returns a block with the given id and no state.
+    fn block(id: BlockId) -> Block {
+        Block { id, state: 0 }
+    }
+
+
+    /// Asserts the storage holds the expected block at every index.
+    fn assert_blocks(storage: &PaletteStorage, expected: &[Block]) {
+        for (block_index, block) in expected.iter().enumerate() {
+            assert!(storage.get(block_index) == *block, "block at index {block_index} differs");
+        }
+    }
+
+
+    #[test]
+    fn uniform_storage_reads_without_indices() {
+        let mut storage = PaletteStorage::filled(block(3));
+        assert!(storage.uniform() == Some(block(3)));
+        assert!(storage.get(0) == block(3) && storage.get(CHUNK_VOLUME - 1) == block(3));
+        assert_eq!(storage.raw().1, 0);
+        assert!(storage.raw().2.is_empty());
+
+        // Setting the block already stored keeps the fast path
+        storage.set(10, block(3));
+        assert!(storage.uniform() == Some(block(3)));
+        assert!(storage.raw().2.is_empty());
+
+        storage.set(10, block(4));
+        assert!(storage.uniform().is_none());
+        assert!(storage.get(10) == block(4) && storage.get(11) == block(3));
+    }
+
+
+    #[test]
+    fn growing_past_index_widths_keeps_every_block() {
+        let mut storage = PaletteStorage::filled(block(0));
+        let mut expected = vec![block(0); CHUNK_VOLUME];
+
+        // Seventeen ids cross the one, two, four and five bit index boundaries
+        for id in 1..=17 {
+            for block_index in (id as usize..CHUNK_VOLUME).step_by(37 + id as usize) {
+                storage.set(block_index, block(id));
+                expected[block_index] = block(id);
+            }
+
+            assert_eq!(storage.raw().1, bits_for(id as usize + 1), "index width after {} palette entries", id + 1);
+            assert_blocks(&storage, &expected);
+        }
+    }
+
+
+    #[test]
+    fn compact_drops_unused_entries_and_shrinks_indices() {
+        let mut storage = PaletteStorage::filled(block(0));
+        for id in 1..=4 {
+            storage.set(id as usize * 100, block(id));
+        }
+        assert_eq!(storage.raw().1, 3);
+
+        // Only air and one other block remain in use
+        for id in 2..=4 {
+            storage.set(id as usize * 100, block(0));
+        }
+        storage.compact();
+
+        let (palette, bits_per_index, _data) = storage.raw();
+        assert_eq!(palette.len(), 2);
+        assert_eq!(bits_per_index, 1);
+        assert!(storage.get(100) == block(1));
+        assert!(storage.get(200) == block(0) && storage.get(400) == block(0));
+
+        // Clearing the last other block collapses the storage to uniform
+        storage.set(100, block(0));
+        storage.compact();
+        assert!(storage.uniform() == Some(block(0)));
+    }
+
+
+    #[test]
+    fn raw_parts_rebuild_the_same_storage() {
+        let mut storage = PaletteStorage::filled(block(1));
+        for block_index in (0..CHUNK_VOLUME).step_by(5) {
+            storage.set(block_index, block((block_index % 6) as BlockId + 2));
+        }
+
+        let (palette, bits_per_index, data) = storage.raw();
+        let rebuilt = PaletteStorage::from_raw(palette.to_vec(), bits_per_index, data.to_vec()).unwrap();
+        for block_index in 0..CHUNK_VOLUME {
+            assert!(rebuilt.get(block_index) == storage.get(block_index), "block at index {block_index} differs");
+        }
+
+        let uniform = PaletteStorage::filled(block(7));
+        let (palette, bits_per_index, data) = uniform.raw();
+        let rebuilt = PaletteStorage::from_raw(palette.to_vec(), bits_per_index, data.to_vec()).unwrap();
+        assert!(rebuilt.uniform() == Some(block(7)));
+    }
+
+
+    #[test]
+    fn inconsistent_raw_parts_are_rejected() {
+        let mut storage = PaletteStorage::filled(block(0));
+        storage.set(0, block(1));
+        storage.set(1, block(2));
+        let (palette, bits_per_index, data) = storage.raw();
+
+        // Too few words, an empty palette and a palette too long for its index width
+        assert!(PaletteStorage::from_raw(palette.to_vec(), bits_per_index, data[1..].to_vec()).is_none());
+        assert!(PaletteStorage::from_raw(Vec::new(), 0, Vec::new()).is_none());
+        assert!(PaletteStorage::from_raw(vec![block(0); 3], 1, data.to_vec()).is_none());
+
+        // An index pointing past the end of the palette
+        let mut out_of_range = data.to_vec();
+        out_of_range[0] |= 0b11;
+        assert!(PaletteStorage::from_raw(palette.to_vec(), bits_per_index, out_of_range).is_none());
+    }
+}
@@ -6,11 +6,36 @@ use bevy::prelude::*;
 use EulerRot::YXZ;
 use glam::IVec3;
 
-use crate::chunk::*;
+use crate::registry::*;
+use crate::material::*;
+use crate::lighting::*;
+use crate::gravity::*;
+use crate::fluid::*;
+use crate::raycast::*;
+use crate::region::*;
 use crate::block::*;
 use crate::world::*;
 
 
+const PLAYER_RADIUS: f32 = 1.0;
+const SWIM_SPEED_FACTOR: f32 = 0.5;
+const SWIM_UP_SPEED: f32 = 4.0;
+const SINK_ACCELERATION: f32 = 4.0;
+const MAX_SINK_SPEED: f32 = 2.0;
+const HOTBAR: [(KeyCode, &str); 10] = [
+    (KeyCode::Digit1, "grass"),
+    (KeyCode::Digit2, "dirt"),
+    (KeyCode::Digit3, "stone"),
+    (KeyCode::Digit4, "coal_ore"),
+    (KeyCode::Digit5, "glowstone"),
+    (KeyCode::Digit6, "glass"),
+    (KeyCode::Digit7, "ice"),
+    (KeyCode::Digit8, "water"),
+    (KeyCode::Digit9, "sand"),
+    (KeyCode::Digit0, "torch"),
+];
+
+
 // Player component
 #[derive(Component)]
 pub struct Player;
@@ -43,6 +68,7 @@ pub struct JumpState {
 pub struct BlockActions {
     pub should_destroy: bool,
     pub should_place: bool,
+    pub selected_block: &'static str,
 }
 
 
@@ -83,6 +109,7 @@ impl BlockActions {
         Self {
             should_destroy: false,
             should_place: false,
+            selected_block: "stone",
         }
     }
 }
@@ -110,7 +137,7 @@ pub fn spawn_player(
         Transform::from_translation(spawn_location),
         GlobalTransform::default(),
         RigidBody::Dynamic,
-        Collider::sphere(1.0),
+        Collider::sphere(PLAYER_RADIUS),
         LinearVelocity::default(),
         Restitution::new(0.0),
         LockedAxes::ROTATION_LOCKED,
@@ -145,10 +172,12 @@ pub fn spawn_player(
 
 /// Handles player movement.
 pub fn player_movement(
-    mut query: Query<&mut LinearVelocity, With<Player>>,
+    mut query: Query<(&mut LinearVelocity, &Transform), With<Player>>,
     camera_query: Query<&Transform, With<Camera3d>>,
     input: Res<ButtonInput<KeyCode>>,
     player_settings: Res<PlayerSettings>,
+    registry: Res<BlockRegistry>,
+    world: Res<WorldChunks>,
     mut jump_state: ResMut<JumpState>,
     time: Res<Time>,
 ) {
@@ -179,7 +208,25 @@ pub fn player_movement(
 
     let horizontal_velocity = (forward * direction.z + right * direction.x) * speed;
 
-    for mut linear_velocity in query.iter_mut() {
+    for (mut linear_velocity, transform) in query.iter_mut() {
+        let block_position = get_block_position(transform.translation);
+        let swimming = world.get_block(block_position).is_some_and(|block| registry.is_fluid(block));
+
+        // Fluids slow the player down, let them sink slowly and swim up while jump is held
+        if swimming {
+            linear_velocity.x = horizontal_velocity.x * SWIM_SPEED_FACTOR;
+            linear_velocity.z = horizontal_velocity.z * SWIM_SPEED_FACTOR;
+
+            if input.pressed(KeyCode::Space) {
+                linear_velocity.y = SWIM_UP_SPEED;
+            }
+            else {
+                linear_velocity.y = (linear_velocity.y - SINK_ACCELERATION * deltatime).max(-MAX_SINK_SPEED);
+            }
+            jump_state.should_jump = false;
+            continue;
+        }
+
         let grounded = linear_velocity.y.abs() < 0.05;
 
         // Apply horizontal movement
@@ -226,59 +273,114 @@ pub fn camera_look(
 }
 
 
-/// Destroys selected block within chunk.
+/// Destroys the targeted block.
 pub fn destroy_block(
     mouse_input: Res<ButtonInput<MouseButton>>,
-    camera_query: Query<&GlobalTransform, With<Camera3d>>,
+    targeted_block: Res<TargetedBlock>,
+    registry: Res<BlockRegistry>,
+    chunk_materials: Res<ChunkMaterials>,
     mut world: ResMut<WorldChunks>,
-    chunk_entities: Res<ChunkEntities>,
+    mut chunk_entities: ResMut<ChunkEntities>,
+    mut chunk_tasks: ResMut<ChunkTasks>,
+    mut world_save: ResMut<WorldSave>,
+    mut fluid_ticks: ResMut<FluidTicks>,
+    mut gravity_checks: ResMut<GravityChecks>,
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut block_state: ResMut<BlockActions>,
 ) {
     if mouse_input.just_pressed(MouseButton::Left) {
-        let player_transform = match camera_query.single() {
-            Ok(t) => t,
-            Err(_) => return,
-        };
-        let player_position = player_transform.translation();
+        let Some(hit) = targeted_block.0 else { return; };
+
+        // Blocks with negative hardness can not be broken
+        let block = world.get_block(hit.block_position).unwrap_or_default();
+        if registry.get(block).hardness < 0.0 {
+            return;
+        }
+
+        if let Some(chunk_position) = world.set_block(hit.block_position, Block::default()) {
+            world_save.modified.insert(chunk_position);
+            fluid_ticks.schedule_around(hit.block_position);
+            gravity_checks.schedule_around(hit.block_position);
+            let relit_chunks = update_block_light(&mut world, &registry, hit.block_position);
+            chunk_tasks.remesh_all(relit_chunks.into_iter().filter(|relit| *relit != chunk_position));
+            rebuild_chunk(chunk_position, &world, &registry, &chunk_materials, &mut chunk_entities, &mut chunk_tasks, &mut meshes, &mut commands);
+        }
+        block_state.should_destroy = false;
+    }
+}
 
-        let block_x = player_position.x.floor() as i32;
-        let block_y = (player_position.y - 2.0).floor() as i32;
-        let block_z = player_position.z.floor() as i32;
 
-        let chunk_x = block_x.div_euclid(CHUNK_SIZE_X as i32);
-        let chunk_y = block_y.div_euclid(CHUNK_SIZE_Y as i32);
-        let chunk_z = block_z.div_euclid(CHUNK_SIZE_Z as i32);
+/// Selects the block type to place with the number keys.
+pub fn select_block(
+    input: Res<ButtonInput<KeyCode>>,
+    mut block_state: ResMut<BlockActions>,
+) {
+    for (key, block_name) in HOTBAR {
+        if input.just_pressed(key) {
+            block_state.selected_block = block_name;
+        }
+    }
+}
 
-        let local_x = block_x.rem_euclid(CHUNK_SIZE_X as i32) as usize;
-        let local_y = block_y.rem_euclid(CHUNK_SIZE_Y as i32) as usize;
-        let local_z = block_z.rem_euclid(CHUNK_SIZE_Z as i32) as usize;
 
-        let chunk_position = IVec3::new(chunk_x, chunk_y, chunk_z);
+/// Places the selected block against the targeted face unless it would overlap the player.
+pub fn place_block(
+    mouse_input: Res<ButtonInput<MouseButton>>,
+    targeted_block: Res<TargetedBlock>,
+    player_query: Query<&Transform, With<Player>>,
+    registry: Res<BlockRegistry>,
+    chunk_materials: Res<ChunkMaterials>,
+    mut world: ResMut<WorldChunks>,
+    mut chunk_entities: ResMut<ChunkEntities>,
+    mut chunk_tasks: ResMut<ChunkTasks>,
+    mut world_save: ResMut<WorldSave>,
+    mut fluid_ticks: ResMut<FluidTicks>,
+    mut gravity_checks: ResMut<GravityChecks>,
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut block_state: ResMut<BlockActions>,
+) {
+    if mouse_input.just_pressed(MouseButton::Right) {
+        let Some(hit) = targeted_block.0 else { return; };
+        let Ok(player_transform) = player_query.single() else { return; };
 
-        if let Some(chunk) = world.chunks.get_mut(&chunk_position) {
-            let block_index = Chunk::get_index(local_x, local_y, local_z);
-            chunk.blocks[block_index] = Block::default(); 
+        // Rays starting inside a block have no face to place against
+        if hit.normal == IVec3::ZERO {
+            return;
+        }
 
-            // Rebuild mesh with updated chunk
-            let mesh = build_mesh(chunk_position, &world);
-            let mesh_handle = meshes.add(mesh);
+        let block_position = hit.block_position + hit.normal;
+        if world.get_block(block_position).is_none_or(|block| registry.is_solid(block)) {
+            return;
+        }
 
-            if let Some(entity) = chunk_entities.map.get(&chunk_position) {
-                commands.entity(*entity).insert(Mesh3d(mesh_handle));
-            }
+        if intersects_player(block_position, player_transform.translation) {
+            return;
+        }
 
-            // Update collider
-            let points = get_points(&chunk_position, &world);
-            if let Some(entity) = chunk_entities.colliders.get(&chunk_position) {
-                commands
-                    .entity(*entity)
-                    .insert(Collider::voxels_from_points(Vec3::splat(1.0), &points));
-            }
+        let block = registry.block(block_state.selected_block);
+        if block == Block::AIR {
+            return;
+        }
 
-            update_chunks(chunk_position, &world, &chunk_entities, &mut meshes, &mut commands);
+        if let Some(chunk_position) = world.set_block(block_position, block) {
+            world_save.modified.insert(chunk_position);
+            fluid_ticks.schedule_around(block_position);
+            gravity_checks.schedule_around(block_position);
+            let relit_chunks = update_block_light(&mut world, &registry, block_position);
+            chunk_tasks.remesh_all(relit_chunks.into_iter().filter(|relit| *relit != chunk_position));
+            rebuild_chunk(chunk_position, &world, &registry, &chunk_materials, &mut chunk_entities, &mut chunk_tasks, &mut meshes, &mut commands);
         }
-        block_state.should_destroy = false;
+        block_state.should_place = false;
     }
-}
\ No newline at end of file
+}
+
+
+/// Returns true when a block at the given position would overlap the players collider.
+fn intersects_player(block_position: IVec3, player_position: Vec3) -> bool {
+    let block_min = Vec3::new(block_position.x as f32, block_position.y as f32, block_position.z as f32);
+    let closest_point = player_position.clamp(block_min, block_min + Vec3::ONE);
+
+    closest_point.distance_squared(player_position) < PLAYER_RADIUS * PLAYER_RADIUS
+}
@@ -0,0 +1,230 @@
+use bevy::prelude::*;
+use glam::IVec3;
+
+use crate::registry::*;
+use crate::world::*;
+
+
+pub const MAX_REACH: f32 = 6.0;
+
+
+// Block hit by a ray cast through the world
+#[derive(Clone, Copy)]
+pub struct RaycastHit {
+    pub block_position: IVec3,
+    pub normal: IVec3,
+    pub distance: f32,
+}
+
+
+// Block the player is looking at this frame
+#[derive(Resource, Default)]
+pub struct TargetedBlock(pub Option<RaycastHit>);
+
+
+/// Steps through the voxel grid along a ray and returns the first solid block within reach.
+pub fn raycast(
+    world: &WorldChunks, 
+    registry: &BlockRegistry, 
+    origin: Vec3, 
+    direction: Vec3, 
+    max_distance: f32,
+) -> Option<RaycastHit> {
+    let direction = direction.normalize_or_zero();
+    if direction == Vec3::ZERO {
+        return None;
+    }
+
+    let origin = origin.to_array();
+    let direction = direction.to_array();
+    let mut block_position = origin.map(|value| value.floor() as i32);
+    let mut step = [0; 3];
+    let mut next_boundary = [f32::INFINITY; 3];
+    let mut boundary_spacing = [f32::INFINITY; 3];
+
+    // Distance along the ray to the first boundary on each axis and between boundaries after that
+    for axis in 0..3 {
+        if direction[axis] > 0.0 {
+            step[axis] = 1;
+            next_boundary[axis] = (block_position[axis] as f32 + 1.0 - origin[axis]) / direction[axis];
+            boundary_spacing[axis] = 1.0 / direction[axis];
+        } 
+        else if direction[axis] < 0.0 {
+            step[axis] = -1;
+            next_boundary[axis] = (origin[axis] - block_position[axis] as f32) / -direction[axis];
+            boundary_spacing[axis] = -1.0 / direction[axis];
+        }
+    }
+
+    let mut normal = [0; 3];
+    let mut distance = 0.0;
+
+    while distance <= max_distance {
+        let position = IVec3::from_array(block_position);
+        if world.get_block(position).is_some_and(|block| registry.is_solid(block)) {
+            return Some(RaycastHit {
+                block_position: position,
+                normal: IVec3::from_array(normal),
+                distance,
+            });
+        }
+
+        // Cross whichever boundary the ray reaches first
+        let axis = (0..3)
+            .min_by(|a, b| next_boundary[*a].total_cmp(&next_boundary[*b]))
+            .unwrap();
+
+        distance = next_boundary[axis];
+        block_position[axis] += step[axis];
+        next_boundary[axis] += boundary_spacing[axis];
+        normal = [0; 3];
+        normal[axis] = -step[axis];
+    }
+
+    None
+}
+
+
+/// Updates the targeted block from the cameras view direction.
+pub fn update_targeted_block(
+    camera_query: Query<&GlobalTransform, With<Camera3d>>,
+    world: Res<WorldChunks>,
+    registry: Res<BlockRegistry>,
+    mut targeted_block: ResMut<TargetedBlock>,
+) {
+    let camera_transform = if let Ok(camera) = camera_query.single() { camera } 
+    else { return; };
+
+    targeted_block.0 = raycast(
+        &world, 
+        &registry,
+        camera_transform.translation(), 
+        *camera_transform.forward(), 
+        MAX_REACH,
+    );
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::block::*;
+    use crate::chunk::*;
+
+    use super::*;
+
+
+    const TEST_BLOCKS: &str = r#"[
+        (name: "air", id: 0, solid: false, opacity: Transparent),
+        (name: "stone", id: 1, textures: All((1, 0))),
+    ]"#;
+
+
+    /// Builds a registry from the test block definitions.
+    fn test_registry() -> BlockRegistry {
+        BlockRegistry::from_definitions(ron::from_str(TEST_BLOCKS).unwrap()).unwrap()
+    }
+
+
+    /// Creates a world of loaded air chunks around the origin with stone at the given positions.
+    fn world_with_stone(stone_positions: &[IVec3]) -> WorldChunks {
+        let mut world = WorldChunks::default();
+        for chunk_x in -1..=1 {
+            for chunk_y in -1..=1 {
+                for chunk_z in -1..=1 {
+                    world.chunks.insert(IVec3::new(chunk_x, chunk_y, chunk_z), Chunk::new());
+                }
+            }
+        }
+
+        for block_position in stone_positions {
+            world.set_block(*block_position, Block { id: 1, state: 0 }).unwrap();
+        }
+        world
+    }
+
+
+    /// Casts a ray through a world with stone at the given positions.
+    fn cast(stone_positions: &[IVec3], origin: Vec3, direction: Vec3) -> Option<RaycastHit> {
+        raycast(&world_with_stone(stone_positions), &test_registry(), origin, direction, MAX_REACH)
+    }
+
+
+    /// Asserts a ray hit the expected block through the expected face at the expected distance.
+    fn assert_hit(hit: Option<RaycastHit>, block_position: IVec3, normal: IVec3, distance: f32) {
+        let hit = hit.expect("ray missed");
+        assert_eq!(hit.block_position, block_position);
+        assert_eq!(hit.normal, normal);
+        assert!((hit.distance - distance).abs() < 1e-5, "hit at {} instead of {distance}", hit.distance);
+    }
+
+
+    #[test]
+    fn axis_aligned_rays_hit_the_face_they_enter() {
+        let centre = Vec3::splat(0.5);
+        let stone = [
+            IVec3::new(3, 0, 0),
+            IVec3::new(0, 4, 0),
+            IVec3::new(0, 0, 2),
+        ];
+
+        assert_hit(cast(&stone, centre, Vec3::X), IVec3::new(3, 0, 0), IVec3::NEG_X, 2.5);
+        assert_hit(cast(&stone, centre, Vec3::Y), IVec3::new(0, 4, 0), IVec3::NEG_Y, 3.5);
+        assert_hit(cast(&stone, centre, Vec3::Z), IVec3::new(0, 0, 2), IVec3::NEG_Z, 1.5);
+    }
+
+
+    #[test]
+    fn negative_rays_cross_into_negative_chunks() {
+        let centre = Vec3::splat(0.5);
+        let stone = [
+            IVec3::new(-3, 0, 0),
+            IVec3::new(0, -2, 0),
+            IVec3::new(0, 0, -5),
+        ];
+
+        assert_hit(cast(&stone, centre, Vec3::NEG_X), IVec3::new(-3, 0, 0), IVec3::X, 2.5);
+        assert_hit(cast(&stone, centre, Vec3::NEG_Y), IVec3::new(0, -2, 0), IVec3::Y, 1.5);
+        assert_hit(cast(&stone, centre, Vec3::NEG_Z), IVec3::new(0, 0, -5), IVec3::Z, 4.5);
+    }
+
+
+    #[test]
+    fn diagonal_rays_report_the_face_they_cross_last() {
+        let centre = Vec3::splat(0.5);
+        let direction = Vec3::new(2.0, 1.0, 0.0);
+        let length = direction.length();
+
+        // Stepping from the centre the ray crosses x = 1 at a quarter of the direction, y = 1 at half and x = 3 at one and a quarter
+        assert_hit(cast(&[IVec3::new(1, 1, 0)], centre, direction), IVec3::new(1, 1, 0), IVec3::NEG_Y, 0.5 * length);
+        assert_hit(cast(&[IVec3::new(3, 1, 0)], centre, direction), IVec3::new(3, 1, 0), IVec3::NEG_X, 1.25 * length);
+
+        // The same ray mirrored into negative x enters through the positive x face
+        let mirrored = Vec3::new(-2.0, 1.0, 0.0);
+        assert_hit(cast(&[IVec3::new(-3, 1, 0)], centre, mirrored), IVec3::new(-3, 1, 0), IVec3::X, 1.25 * length);
+    }
+
+
+    #[test]
+    fn blocks_exactly_at_max_reach_are_hit() {
+        let origin = Vec3::new(0.0, 0.5, 0.5);
+        let reach = MAX_REACH as i32;
+
+        assert_hit(cast(&[IVec3::new(reach, 0, 0)], origin, Vec3::X), IVec3::new(reach, 0, 0), IVec3::NEG_X, MAX_REACH);
+        assert!(cast(&[IVec3::new(reach + 1, 0, 0)], origin, Vec3::X).is_none());
+    }
+
+
+    #[test]
+    fn rays_starting_inside_a_block_hit_it_without_a_face() {
+        let origin = Vec3::new(0.25, 0.5, 0.75);
+
+        assert_hit(cast(&[IVec3::ZERO], origin, Vec3::X), IVec3::ZERO, IVec3::ZERO, 0.0);
+    }
+
+
+    #[test]
+    fn rays_through_air_or_without_direction_miss() {
+        assert!(cast(&[], Vec3::splat(0.5), Vec3::new(1.0, -1.0, 0.5)).is_none());
+        assert!(cast(&[IVec3::ZERO], Vec3::splat(0.5), Vec3::ZERO).is_none());
+    }
+}
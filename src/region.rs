@@ -0,0 +1,395 @@
+use std::collections::HashSet;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::path::Path;
+use std::io;
+use std::fs;
+use bevy::prelude::*;
+use glam::IVec3;
+
+use crate::generator::*;
+use crate::registry::*;
+use crate::palette::*;
+use crate::chunk::*;
+use crate::block::*;
+use crate::world::*;
+
+
+const REGION_SIZE: i32 = 8;
+const REGION_CHUNKS: usize = (REGION_SIZE * REGION_SIZE * REGION_SIZE) as usize;
+const HEADER_SIZE: usize = REGION_CHUNKS * 8;
+const FORMAT_VERSION: u8 = 2;
+const STATELESS_FORMAT_VERSION: u8 = 1;
+
+
+// Where edited chunks are saved and which loaded chunks have unsaved edits
+#[derive(Resource)]
+pub struct WorldSave {
+    pub directory: PathBuf,
+    pub modified: HashSet<IVec3>,
+}
+
+
+impl WorldSave {
+    /// Keeps saves of each seed in their own directory under the game directory, with a subdirectory for generators other than noise terrain.
+    pub fn for_world(world_seed: &WorldSeed, generator: &ChunkGenerator) -> Self {
+        let mut directory = resolve_game_path(format!("saves/{}", world_seed.0));
+        if let Some(save_name) = generator.0.save_name() {
+            directory.push(save_name);
+        }
+
+        Self {
+            directory,
+            modified: HashSet::new(),
+        }
+    }
+}
+
+
+/// Returns the region containing a chunk.
+fn get_region_position(chunk_position: IVec3) -> IVec3 {
+    chunk_position.div_euclid(IVec3::splat(REGION_SIZE))
+}
+
+
+/// Returns the header slot of a chunk within its region.
+fn get_slot(chunk_position: IVec3) -> usize {
+    let local = chunk_position.rem_euclid(IVec3::splat(REGION_SIZE));
+    (local.x + local.z * REGION_SIZE + local.y * REGION_SIZE * REGION_SIZE) as usize
+}
+
+
+/// Returns the path of a region file.
+fn get_region_path(directory: &Path, region_position: IVec3) -> PathBuf {
+    directory.join(format!("r.{}.{}.{}.region", region_position.x, region_position.y, region_position.z))
+}
+
+
+/// Serializes a chunk into its palette, index width and packed indices.
+pub fn encode_chunk(chunk: &Chunk) -> Vec<u8> {
+    let (palette, bits_per_index, data) = chunk.storage().raw();
+    let mut bytes = Vec::with_capacity(4 + palette.len() * 3 + data.len() * 8);
+
+    bytes.push(FORMAT_VERSION);
+    bytes.extend_from_slice(&(palette.len() as u16).to_le_bytes());
+    for block in palette {
+        bytes.extend_from_slice(&block.id.to_le_bytes());
+        bytes.push(block.state);
+    }
+    bytes.push(bits_per_index as u8);
+    for word in data {
+        bytes.extend_from_slice(&word.to_le_bytes());
+    }
+
+    bytes
+}
+
+
+/// Deserializes a chunk written by encode_chunk.
+pub fn decode_chunk(bytes: &[u8]) -> io::Result<Chunk> {
+    let mut reader = bytes;
+
+    // Chunks saved before blocks had state store ids alone
+    let version = take_bytes(&mut reader, 1)?[0];
+    if version != FORMAT_VERSION && version != STATELESS_FORMAT_VERSION {
+        return Err(invalid_data("unsupported chunk format version"));
+    }
+
+    let palette_length = u16::from_le_bytes(take_bytes(&mut reader, 2)?.try_into().unwrap()) as usize;
+    let mut palette = Vec::with_capacity(palette_length);
+    for _entry in 0..palette_length {
+        let id = u16::from_le_bytes(take_bytes(&mut reader, 2)?.try_into().unwrap());
+        let state = if version == FORMAT_VERSION { take_bytes(&mut reader, 1)?[0] } else { 0 };
+        palette.push(Block { id, state });
+    }
+
+    let bits_per_index = take_bytes(&mut reader, 1)?[0] as usize;
+    if !reader.len().is_multiple_of(8) {
+        return Err(invalid_data("chunk indices are truncated"));
+    }
+
+    let data = reader
+        .chunks_exact(8)
+        .map(|word| u64::from_le_bytes(word.try_into().unwrap()))
+        .collect();
+
+    PaletteStorage::from_raw(palette, bits_per_index, data)
+        .map(Chunk::from_storage)
+        .ok_or_else(|| invalid_data("chunk palette does not match its indices"))
+}
+
+
+/// Splits the next bytes off the front of a reader.
+fn take_bytes<'a>(reader: &mut &'a [u8], count: usize) -> io::Result<&'a [u8]> {
+    if reader.len() < count {
+        return Err(invalid_data("chunk data is truncated"));
+    }
+
+    let (taken, rest) = reader.split_at(count);
+    *reader = rest;
+    Ok(taken)
+}
+
+
+/// Creates an error for malformed save data.
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+
+/// Reads every stored chunk entry of a region file, or none when the file does not exist.
+fn read_region(path: &Path) -> io::Result<Vec<Option<Vec<u8>>>> {
+    let mut entries = vec![None; REGION_CHUNKS];
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(entries),
+        Err(error) => return Err(error),
+    };
+
+    if bytes.len() < HEADER_SIZE {
+        return Err(invalid_data("region header is truncated"));
+    }
+
+    for (slot, entry) in entries.iter_mut().enumerate() {
+        if let Some((offset, length)) = read_header_entry(&bytes, slot) {
+            let data = bytes.get(offset..offset + length)
+                .ok_or_else(|| invalid_data("region entry is out of bounds"))?;
+            *entry = Some(data.to_vec());
+        }
+    }
+
+    Ok(entries)
+}
+
+
+/// Returns the byte range of a header slot, or None when the slot is empty.
+fn read_header_entry(bytes: &[u8], slot: usize) -> Option<(usize, usize)> {
+    let header = &bytes[slot * 8..slot * 8 + 8];
+    let offset = u32::from_le_bytes(header[0..4].try_into().unwrap()) as usize;
+    let length = u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize;
+
+    if offset == 0 { None } else { Some((offset, length)) }
+}
+
+
+/// Writes a region file with a header index followed by each chunks data.
+fn write_region(path: &Path, entries: &[Option<Vec<u8>>]) -> io::Result<()> {
+    let mut header = Vec::with_capacity(HEADER_SIZE);
+    let mut body = Vec::new();
+
+    for entry in entries {
+        match entry {
+            Some(data) => {
+                header.extend_from_slice(&((HEADER_SIZE + body.len()) as u32).to_le_bytes());
+                header.extend_from_slice(&(data.len() as u32).to_le_bytes());
+                body.extend_from_slice(data);
+            }
+            None => header.extend_from_slice(&[0; 8]),
+        }
+    }
+
+    // Write beside the region and swap it in so readers never see a partial file
+    let temporary_path = path.with_extension("tmp");
+    header.extend_from_slice(&body);
+    fs::write(&temporary_path, header)?;
+    fs::rename(temporary_path, path)
+}
+
+
+/// Saves chunks into their region files, keeping the other chunks already stored there.
+pub fn save_chunks<'a>(directory: &Path, chunks: impl IntoIterator<Item = (IVec3, &'a Chunk)>) -> io::Result<()> {
+    let mut regions: HashMap<IVec3, Vec<(usize, Vec<u8>)>> = HashMap::new();
+    for (chunk_position, chunk) in chunks {
+        regions
+            .entry(get_region_position(chunk_position))
+            .or_default()
+            .push((get_slot(chunk_position), encode_chunk(chunk)));
+    }
+
+    if regions.is_empty() {
+        return Ok(());
+    }
+
+    fs::create_dir_all(directory)?;
+    for (region_position, updates) in regions {
+        let path = get_region_path(directory, region_position);
+        let mut entries = match read_region(&path) {
+            Ok(entries) => entries,
+            // A damaged region is kept aside for recovery so it does not block every later save into it
+            Err(error) if error.kind() == io::ErrorKind::InvalidData => {
+                warn!("Region {} is damaged ({error}), moving it aside and starting a new one", path.display());
+                fs::rename(&path, path.with_extension("damaged"))?;
+                vec![None; REGION_CHUNKS]
+            }
+            Err(error) => return Err(error),
+        };
+        for (slot, data) in updates {
+            entries[slot] = Some(data);
+        }
+        write_region(&path, &entries)?;
+    }
+
+    Ok(())
+}
+
+
+/// Loads a saved chunk, or None when it was never saved.
+pub fn load_chunk(directory: &Path, chunk_position: IVec3) -> io::Result<Option<Chunk>> {
+    let path = get_region_path(directory, get_region_position(chunk_position));
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(error) => return Err(error),
+    };
+
+    if bytes.len() < HEADER_SIZE {
+        return Err(invalid_data("region header is truncated"));
+    }
+
+    match read_header_entry(&bytes, get_slot(chunk_position)) {
+        Some((offset, length)) => {
+            let data = bytes.get(offset..offset + length)
+                .ok_or_else(|| invalid_data("region entry is out of bounds"))?;
+            decode_chunk(data).map(Some)
+        }
+        None => Ok(None),
+    }
+}
+
+
+/// Saves edited chunks that are about to be unloaded, they stay marked as modified when the save fails.
+pub fn save_unloading_chunks(
+    world: &WorldChunks,
+    unloading: &[IVec3],
+    world_save: &mut WorldSave,
+) {
+    let edited: Vec<IVec3> = unloading
+        .iter()
+        .filter(|chunk_position| world_save.modified.contains(*chunk_position))
+        .copied()
+        .collect();
+
+    let chunks = edited
+        .iter()
+        .filter_map(|chunk_position| world.chunks.get(chunk_position).map(|chunk| (*chunk_position, chunk)));
+
+    match save_chunks(&world_save.directory, chunks) {
+        Ok(()) => {
+            for chunk_position in &edited {
+                world_save.modified.remove(chunk_position);
+            }
+        }
+        Err(error) => error!("Failed to save chunks: {error}"),
+    }
+}
+
+
+/// Saves every edited chunk when the app exits.
+pub fn save_on_exit(
+    mut exit_events: MessageReader<AppExit>,
+    world: Res<WorldChunks>,
+    mut world_save: ResMut<WorldSave>,
+) {
+    if exit_events.read().last().is_none() {
+        return;
+    }
+
+    let modified: Vec<IVec3> = world_save.modified.iter().copied().collect();
+    save_unloading_chunks(&world, &modified, &mut world_save);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    /// Creates an empty save directory unique to a test.
+    fn test_directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("region_test_{}_{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        directory
+    }
+
+
+    /// Asserts two chunks hold the same block at every position.
+    fn assert_same_blocks(expected: &Chunk, actual: &Chunk) {
+        for block_y in 0..CHUNK_SIZE_Y {
+            for block_z in 0..CHUNK_SIZE_Z {
+                for block_x in 0..CHUNK_SIZE_X {
+                    assert!(
+                        expected.get(block_x, block_y, block_z) == actual.get(block_x, block_y, block_z),
+                        "block at {block_x} {block_y} {block_z} differs",
+                    );
+                }
+            }
+        }
+    }
+
+
+    /// Creates a chunk with several block types spread through it.
+    fn mixed_chunk() -> Chunk {
+        let mut chunk = Chunk::new();
+        for block_y in 0..CHUNK_SIZE_Y {
+            for block_z in 0..CHUNK_SIZE_Z {
+                for block_x in 0..CHUNK_SIZE_X {
+                    let id = ((block_x + block_y * 3 + block_z * 7) % 5) as BlockId;
+                    chunk.set(block_x, block_y, block_z, Block { id, state: 0 });
+                }
+            }
+        }
+        chunk
+    }
+
+
+    #[test]
+    fn encoded_chunks_decode_to_the_same_blocks() {
+        let uniform = Chunk::from_storage(PaletteStorage::filled(Block { id: 3, state: 0 }));
+
+        let mut with_state = Chunk::new();
+        with_state.set(0, 0, 0, Block { id: 7, state: 5 });
+        with_state.set(4, 2, 9, Block { id: 7, state: 255 });
+        with_state.set(15, 15, 15, Block { id: 2, state: 1 });
+
+        for chunk in [uniform, mixed_chunk(), with_state] {
+            let decoded = decode_chunk(&encode_chunk(&chunk)).unwrap();
+            assert_same_blocks(&chunk, &decoded);
+        }
+    }
+
+
+    #[test]
+    fn saved_chunks_load_back_from_their_region() {
+        let directory = test_directory("save_load");
+        let first_position = IVec3::new(0, 0, 0);
+        let second_position = IVec3::new(1, 2, 3);
+        let unsaved_position = IVec3::new(2, 0, 0);
+
+        let first = mixed_chunk();
+        let mut second = Chunk::new();
+        second.set(1, 1, 1, Block { id: 4, state: 2 });
+
+        assert_eq!(get_region_position(first_position), get_region_position(second_position));
+        save_chunks(&directory, [(first_position, &first)]).unwrap();
+        save_chunks(&directory, [(second_position, &second)]).unwrap();
+
+        assert_same_blocks(&first, &load_chunk(&directory, first_position).unwrap().unwrap());
+        assert_same_blocks(&second, &load_chunk(&directory, second_position).unwrap().unwrap());
+        assert!(load_chunk(&directory, unsaved_position).unwrap().is_none());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+
+    #[test]
+    fn stateless_chunks_still_decode() {
+        let stone = Block { id: 1, state: 0 };
+        let mut bytes = vec![STATELESS_FORMAT_VERSION];
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&stone.id.to_le_bytes());
+        bytes.push(0);
+
+        let decoded = decode_chunk(&bytes).unwrap();
+        assert_same_blocks(&Chunk::from_storage(PaletteStorage::filled(stone)), &decoded);
+    }
+}
@@ -0,0 +1,226 @@
+use std::collections::HashMap;
+use serde::Deserialize;
+use bevy::prelude::*;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::path::Path;
+use std::env;
+use std::fs;
+
+use crate::block::*;
+
+
+pub const BLOCK_REGISTRY_PATH: &str = "assets/blocks.ron";
+
+
+// Atlas tiles of a block, one tile for every face, separate top, bottom and side tiles or one per face
+#[derive(Deserialize, Clone, Copy)]
+pub enum BlockTextures {
+    All(AtlasTile),
+    Sides {
+        top: AtlasTile,
+        bottom: AtlasTile,
+        sides: AtlasTile,
+    },
+    Faces {
+        front: AtlasTile,
+        back: AtlasTile,
+        left: AtlasTile,
+        right: AtlasTile,
+        top: AtlasTile,
+        bottom: AtlasTile,
+    },
+}
+
+
+// How a block lets light and the view through, which decides culling and render pass
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Opacity {
+    #[default]
+    Opaque,
+    Transparent,
+    Translucent,
+}
+
+
+// Properties of a single block as written in the registry file
+#[derive(Deserialize, Clone)]
+pub struct BlockDefinition {
+    pub name: String,
+    pub id: BlockId,
+    #[serde(default)]
+    pub textures: BlockTextures,
+    #[serde(default = "default_solid")]
+    pub solid: bool,
+    #[serde(default)]
+    pub opacity: Opacity,
+    #[serde(default)]
+    pub hardness: f32,
+    #[serde(default)]
+    pub fluid: bool,
+    #[serde(default)]
+    pub gravity: bool,
+    #[serde(default)]
+    pub light_emission: [u8; 3],
+    #[serde(default = "default_color")]
+    pub color: [u8; 3],
+    #[serde(skip)]
+    pub face_textures: [[f32; 2]; 6],
+}
+
+
+// Every block definition indexed by id, cheap to clone into async tasks
+#[derive(Resource, Clone)]
+pub struct BlockRegistry {
+    blocks: Arc<Vec<Option<BlockDefinition>>>,
+    names: Arc<HashMap<String, BlockId>>,
+}
+
+
+impl Default for BlockTextures {
+    fn default() -> Self {
+        BlockTextures::All((0, 0))
+    }
+}
+
+
+impl BlockTextures {
+    /// Returns the atlas tile of each face in face index order.
+    pub fn face_tiles(&self) -> [AtlasTile; 6] {
+        match *self {
+            BlockTextures::All(tile) => [tile; 6],
+            BlockTextures::Sides { top, bottom, sides } => {
+                [sides, sides, sides, sides, top, bottom]
+            }
+            BlockTextures::Faces { front, back, left, right, top, bottom } => {
+                [front, back, left, right, top, bottom]
+            }
+        }
+    }
+}
+
+
+/// Resolves a path relative to the game directory the same way Bevy finds its asset folder, so data files load from any working directory.
+pub fn resolve_game_path(relative_path: impl AsRef<Path>) -> PathBuf {
+    let base_directory = env::var_os("BEVY_ASSET_ROOT")
+        .or_else(|| env::var_os("CARGO_MANIFEST_DIR"))
+        .map(PathBuf::from)
+        .or_else(|| env::current_exe().ok().and_then(|executable| executable.parent().map(Path::to_path_buf)))
+        .unwrap_or_default();
+
+    base_directory.join(relative_path)
+}
+
+
+fn default_solid() -> bool {
+    true
+}
+
+
+fn default_color() -> [u8; 3] {
+    [128, 128, 128]
+}
+
+
+impl BlockRegistry {
+    /// Loads block definitions from a RON file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path)
+            .map_err(|error| format!("could not read {}: {error}", path.display()))?;
+        let definitions: Vec<BlockDefinition> = ron::from_str(&source)
+            .map_err(|error| format!("could not parse {}: {error}", path.display()))?;
+
+        Self::from_definitions(definitions)
+    }
+
+
+    /// Builds the registry, checking that ids and names are unique and that id 0 is air.
+    pub fn from_definitions(definitions: Vec<BlockDefinition>) -> Result<Self, String> {
+        let mut blocks: Vec<Option<BlockDefinition>> = Vec::new();
+        let mut names = HashMap::new();
+
+        for mut definition in definitions {
+            let id = definition.id as usize;
+            if names.insert(definition.name.clone(), definition.id).is_some() {
+                return Err(format!("block name {} is defined twice", definition.name));
+            }
+            if blocks.len() <= id {
+                blocks.resize(id + 1, None);
+            }
+            if blocks[id].is_some() {
+                return Err(format!("block id {id} is defined twice"));
+            }
+
+            definition.face_textures = definition.textures.face_tiles().map(get_tile_origin);
+            blocks[id] = Some(definition);
+        }
+
+        match blocks.first() {
+            Some(Some(air)) if !air.solid => {}
+            _ => return Err("block id 0 must be a non solid air block".to_string()),
+        }
+
+        Ok(Self {
+            blocks: Arc::new(blocks),
+            names: Arc::new(names),
+        })
+    }
+
+
+    /// Returns the properties of a block, unknown ids behave like air.
+    pub fn get(&self, block: Block) -> &BlockDefinition {
+        match self.blocks.get(block.id as usize) {
+            Some(Some(definition)) => definition,
+            _ => self.blocks[0].as_ref().unwrap(),
+        }
+    }
+
+
+    /// Returns the block with the given name, or air when no block has that name.
+    pub fn block(&self, name: &str) -> Block {
+        self.names
+            .get(name)
+            .map(|id| Block { id: *id, state: 0 })
+            .unwrap_or(Block::AIR)
+    }
+
+
+    /// Returns the block with the given name, or an error when no block has that name.
+    pub fn require(&self, name: &str) -> Result<Block, String> {
+        self.names
+            .get(name)
+            .map(|id| Block { id: *id, state: 0 })
+            .ok_or_else(|| format!("unknown block {name}"))
+    }
+
+
+    /// Returns true when the block collides with the player.
+    pub fn is_solid(&self, block: Block) -> bool {
+        self.get(block).solid
+    }
+
+
+    /// Returns true when the block hides the faces of its neighbours and blocks light.
+    pub fn is_opaque(&self, block: Block) -> bool {
+        self.get(block).opacity == Opacity::Opaque
+    }
+
+
+    /// Returns true when the block flows on the fluid tick.
+    pub fn is_fluid(&self, block: Block) -> bool {
+        self.get(block).fluid
+    }
+
+
+    /// Returns true when the block falls once nothing solid is below it.
+    pub fn has_gravity(&self, block: Block) -> bool {
+        self.get(block).gravity
+    }
+
+
+    /// Returns true when the block is drawn in the alpha blended pass.
+    pub fn is_translucent(&self, block: Block) -> bool {
+        self.get(block).opacity == Opacity::Translucent
+    }
+}
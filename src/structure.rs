@@ -0,0 +1,224 @@
+use glam::IVec3;
+
+use crate::registry::*;
+use crate::biome::*;
+use crate::chunk::*;
+use crate::block::*;
+use crate::cave::*;
+
+
+// Salts mixed into the seed so placement and shape rolls are independent of ores and each other
+const PLACEMENT_SALT: u32 = 0x5F3C_9A11;
+const SHAPE_SALT: u32 = 0x2B7E_1516;
+
+// Feature chances are given per this many columns
+pub const FEATURE_CHANCE_SCALE: u32 = 10_000;
+
+// Furthest any feature reaches from its anchor column, and how far above and below the surface it builds
+const STRUCTURE_REACH: i32 = 3;
+const STRUCTURE_HEIGHT: i32 = 9;
+const STRUCTURE_DEPTH: i32 = 3;
+
+
+// Decorations world generation places on top of the terrain
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    Tree,
+    Boulder,
+    Ruin,
+}
+
+
+// Blocks the features are built from, resolved once when the generator is created
+#[derive(Clone, Copy)]
+pub struct StructureBlocks {
+    log: Block,
+    leaves: Block,
+    stone: Block,
+    cobblestone: Block,
+}
+
+
+// Writes feature blocks into a chunk, dropping the ones that fall outside of it
+struct StructureWriter<'a> {
+    chunk: &'a mut Chunk,
+    chunk_min: IVec3,
+}
+
+
+impl StructureBlocks {
+    /// Looks up the feature blocks in the registry, failing when one is missing.
+    pub fn resolve(registry: &BlockRegistry) -> Result<Self, String> {
+        Ok(Self {
+            log: registry.require("log")?,
+            leaves: registry.require("leaves")?,
+            stone: registry.require("stone")?,
+            cobblestone: registry.require("cobblestone")?,
+        })
+    }
+}
+
+
+impl StructureWriter<'_> {
+    /// Sets a block if it lies in the chunk, only filling air unless replace is set.
+    fn place(&mut self, world_position: IVec3, block: Block, replace: bool) {
+        let local = world_position - self.chunk_min;
+        if local.x < 0 || local.y < 0 || local.z < 0
+            || local.x >= CHUNK_SIZE_X as i32 || local.y >= CHUNK_SIZE_Y as i32 || local.z >= CHUNK_SIZE_Z as i32 {
+            return;
+        }
+
+        let (block_x, block_y, block_z) = (local.x as usize, local.y as usize, local.z as usize);
+        if replace || self.chunk.get(block_x, block_y, block_z) == Block::AIR {
+            self.chunk.set(block_x, block_y, block_z, block);
+        }
+    }
+}
+
+
+impl Feature {
+    /// Builds the feature standing on the block below its anchor.
+    fn build(self, writer: &mut StructureWriter, anchor: IVec3, shape: u32, blocks: &StructureBlocks) {
+        match self {
+            Feature::Tree => build_tree(writer, anchor, shape, blocks),
+            Feature::Boulder => build_boulder(writer, anchor, shape, blocks),
+            Feature::Ruin => build_ruin(writer, anchor, shape, blocks),
+        }
+    }
+}
+
+
+/// Places every feature whose anchor column is close enough to reach into the chunk,
+/// skipping columns whose surface block the cave carver removed.
+pub fn place_structures(
+    chunk: &mut Chunk,
+    chunk_position: &IVec3,
+    seed: u32,
+    blocks: &StructureBlocks,
+    column_at: impl Fn(i32, i32) -> BiomeColumn,
+    cave_carver: Option<&CaveCarver>,
+) {
+    let chunk_size = IVec3::new(CHUNK_SIZE_X as i32, CHUNK_SIZE_Y as i32, CHUNK_SIZE_Z as i32);
+    let chunk_min = *chunk_position * chunk_size;
+    let chunk_max = chunk_min + chunk_size;
+    let mut writer = StructureWriter { chunk, chunk_min };
+
+    // Columns rolling above every biomes total chance never hold a feature, so their height is never sampled
+    let max_feature_chance = Biome::ALL
+        .iter()
+        .map(|biome| biome.profile().features.iter().map(|(_, chance)| chance).sum::<u32>())
+        .max()
+        .unwrap_or(0);
+
+    // Anchors are visited in a fixed order so overlapping features resolve the same way in every chunk
+    for world_z in chunk_min.z - STRUCTURE_REACH..chunk_max.z + STRUCTURE_REACH {
+        for world_x in chunk_min.x - STRUCTURE_REACH..chunk_max.x + STRUCTURE_REACH {
+            let column_position = IVec3::new(world_x, 0, world_z);
+            let placement_roll = hash_position(seed ^ PLACEMENT_SALT, column_position) % FEATURE_CHANCE_SCALE;
+            if placement_roll >= max_feature_chance {
+                continue;
+            }
+
+            let column = column_at(world_x, world_z);
+            let anchor = IVec3::new(world_x, column.height + 1, world_z);
+            if anchor.y - STRUCTURE_DEPTH >= chunk_max.y || anchor.y + STRUCTURE_HEIGHT < chunk_min.y {
+                continue;
+            }
+
+            let Some(feature) = pick_feature(column.biome.profile().features, placement_roll) else { continue; };
+
+            // The ground is checked against the carver rather than the chunk since anchors may lie in neighbouring chunks
+            if cave_carver.is_some_and(|cave_carver| cave_carver.carves_surface(world_x, world_z, column.height)) {
+                continue;
+            }
+            let shape = hash_position(seed ^ SHAPE_SALT, anchor);
+            feature.build(&mut writer, anchor, shape, blocks);
+        }
+    }
+}
+
+
+/// Returns the feature a placement roll lands on, given chances per FEATURE_CHANCE_SCALE columns.
+fn pick_feature(features: &[(Feature, u32)], placement_roll: u32) -> Option<Feature> {
+    let mut cumulative_chance = 0;
+    for (feature, chance) in features {
+        cumulative_chance += chance;
+        if placement_roll < cumulative_chance {
+            return Some(*feature);
+        }
+    }
+
+    None
+}
+
+
+/// Builds a tree with a four to six block trunk and a rounded leaf canopy.
+fn build_tree(writer: &mut StructureWriter, anchor: IVec3, shape: u32, blocks: &StructureBlocks) {
+    let trunk_height = 4 + (shape % 3) as i32;
+    let top = anchor.y + trunk_height;
+
+    // Two wide layers around the upper trunk and two narrow ones above, with some corners left out
+    for layer in -2..=1 {
+        let radius: i32 = if layer < 0 { 2 } else { 1 };
+        for offset_z in -radius..=radius {
+            for offset_x in -radius..=radius {
+                let corner = offset_x.abs() == radius && offset_z.abs() == radius;
+                let corner_bit = ((offset_x + 2) * 5 + offset_z + 2 + layer * 25) as u32 % 32;
+                if corner && (layer == 1 || (shape >> corner_bit) & 1 == 0) {
+                    continue;
+                }
+
+                writer.place(IVec3::new(anchor.x + offset_x, top + layer, anchor.z + offset_z), blocks.leaves, false);
+            }
+        }
+    }
+
+    for trunk_y in anchor.y..top {
+        writer.place(IVec3::new(anchor.x, trunk_y, anchor.z), blocks.log, true);
+    }
+}
+
+
+/// Builds a lumpy stone ball half sunk into the ground.
+fn build_boulder(writer: &mut StructureWriter, anchor: IVec3, shape: u32, blocks: &StructureBlocks) {
+    let radius = 1.0 + (shape % 3) as f32 * 0.5;
+    let center = anchor - IVec3::Y;
+    let reach = radius.ceil() as i32;
+
+    for offset_y in -reach..=reach {
+        for offset_z in -reach..=reach {
+            for offset_x in -reach..=reach {
+                let offset = IVec3::new(offset_x, offset_y, offset_z);
+                let lump = hash_position(shape, offset) % 100;
+                let distance = (offset.length_squared() as f32).sqrt() + lump as f32 / 200.0;
+                if distance <= radius {
+                    writer.place(center + offset, blocks.stone, true);
+                }
+            }
+        }
+    }
+}
+
+
+/// Builds the crumbling cobblestone walls of a small square ruin.
+fn build_ruin(writer: &mut StructureWriter, anchor: IVec3, shape: u32, blocks: &StructureBlocks) {
+    let half_size = 2 + (shape % 2) as i32;
+
+    for offset_z in -half_size..=half_size {
+        for offset_x in -half_size..=half_size {
+            let column = IVec3::new(anchor.x + offset_x, anchor.y, anchor.z + offset_z);
+            let on_wall = offset_x.abs() == half_size || offset_z.abs() == half_size;
+
+            // A floor one block down, and walls that crumble to a random height
+            writer.place(column - IVec3::Y, blocks.cobblestone, true);
+            if !on_wall {
+                continue;
+            }
+
+            let wall_height = (hash_position(shape, column) % 4) as i32;
+            for wall_y in 0..wall_height {
+                writer.place(column + IVec3::Y * wall_y, blocks.cobblestone, true);
+            }
+        }
+    }
+}
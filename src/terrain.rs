@@ -0,0 +1,147 @@
+use fastnoise_lite::{DomainWarpType, FastNoiseLite, FractalType, NoiseType};
+use serde::Deserialize;
+use bevy::prelude::*;
+use std::path::Path;
+use std::fs;
+use glam::IVec3;
+
+use crate::biome::*;
+
+
+pub const TERRAIN_SETTINGS_PATH: &str = "assets/terrain.ron";
+
+
+// Fractal layering applied to the terrain height noise
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TerrainFractal {
+    #[default]
+    None,
+    FBm,
+    Ridged,
+    PingPong,
+}
+
+
+// Shape of the terrain height noise, read from the terrain settings file, the noise generator rebuilds its noise when it changes
+#[derive(Resource, Deserialize, Clone)]
+#[serde(default)]
+pub struct TerrainNoiseSettings {
+    pub frequency: f32,
+    pub fractal: TerrainFractal,
+    pub octaves: i32,
+    pub lacunarity: f32,
+    pub gain: f32,
+    pub warp_amplitude: f32,
+    pub warp_frequency: f32,
+    pub height_scale: f32,
+    pub height_offset: f32,
+}
+
+
+// Terrain and climate noise built once for a seed and shared by every chunk
+pub struct TerrainNoise {
+    height: FastNoiseLite,
+    warp: Option<FastNoiseLite>,
+    biome_map: BiomeMap,
+    height_scale: f32,
+    height_offset: f32,
+}
+
+
+impl Default for TerrainNoiseSettings {
+    fn default() -> Self {
+        Self {
+            frequency: 0.065,
+            fractal: TerrainFractal::None,
+            octaves: 1,
+            lacunarity: 2.0,
+            gain: 0.5,
+            warp_amplitude: 0.0,
+            warp_frequency: 0.01,
+            height_scale: 1.0,
+            height_offset: 0.0,
+        }
+    }
+}
+
+
+impl TerrainFractal {
+    /// Returns the matching noise library fractal type.
+    fn fractal_type(self) -> FractalType {
+        match self {
+            TerrainFractal::None => FractalType::None,
+            TerrainFractal::FBm => FractalType::FBm,
+            TerrainFractal::Ridged => FractalType::Ridged,
+            TerrainFractal::PingPong => FractalType::PingPong,
+        }
+    }
+}
+
+
+impl TerrainNoiseSettings {
+    /// Loads terrain noise settings from a RON file, fields left out keep their defaults.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path)
+            .map_err(|error| format!("could not read {}: {error}", path.display()))?;
+
+        ron::from_str(&source).map_err(|error| format!("could not parse {}: {error}", path.display()))
+    }
+}
+
+
+impl TerrainNoise {
+    /// Builds the terrain noise for a seed.
+    pub fn new(seed: u32, settings: &TerrainNoiseSettings) -> Self {
+        let mut height = FastNoiseLite::new();
+        height.set_noise_type(Some(NoiseType::Perlin));
+        height.set_seed(Some(seed as i32));
+        height.set_frequency(Some(settings.frequency));
+        height.set_fractal_type(Some(settings.fractal.fractal_type()));
+        height.set_fractal_octaves(Some(settings.octaves));
+        height.set_fractal_lacunarity(Some(settings.lacunarity));
+        height.set_fractal_gain(Some(settings.gain));
+
+        // Domain warping bends the sampled coordinates so ridges and valleys meander
+        let warp = (settings.warp_amplitude > 0.0).then(|| {
+            let mut warp = FastNoiseLite::new();
+            warp.set_seed(Some(seed as i32));
+            warp.set_domain_warp_type(Some(DomainWarpType::OpenSimplex2));
+            warp.set_domain_warp_amp(Some(settings.warp_amplitude));
+            warp.set_frequency(Some(settings.warp_frequency));
+            warp
+        });
+
+        Self {
+            height,
+            warp,
+            biome_map: BiomeMap::new(seed),
+            height_scale: settings.height_scale,
+            height_offset: settings.height_offset,
+        }
+    }
+
+
+    /// Returns the biome of a column without working out its height.
+    pub fn biome_at(&self, world_x: i32, world_z: i32) -> Biome {
+        self.biome_map.biome_at(IVec3::new(world_x, 0, world_z))
+    }
+
+
+    /// Returns the biome and surface height of a column.
+    pub fn column_at(&self, world_x: i32, world_z: i32) -> BiomeColumn {
+        let (world_x, world_z) = (world_x as f32, world_z as f32);
+        let (noise_x, noise_z) = match &self.warp {
+            Some(warp) => warp.domain_warp_2d(world_x, world_z),
+            None => (world_x, world_z),
+        };
+
+        let terrain_noise = self.height.get_noise_2d(noise_x, noise_z);
+        let (biome, height) = self.biome_map.blended_height(world_x, world_z, terrain_noise);
+
+        BiomeColumn {
+            biome,
+            height: (height * self.height_scale + self.height_offset).floor() as i32,
+        }
+    }
+}
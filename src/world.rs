@@ -1,19 +1,43 @@
 //use bevy::pbr::wireframe::WireframeColor;
 //use bevy::pbr::wireframe::Wireframe;
+use std::collections::BinaryHeap;
 use std::collections::VecDeque;
 use std::collections::HashSet;
 use std::collections::HashMap;
+use std::cmp::Ordering;
+use bevy::tasks::futures::check_ready;
+use bevy::tasks::AsyncComputeTaskPool;
+use bevy::tasks::Task;
+use bevy::mesh::Indices;
 use avian3d::prelude::*;
 use bevy::prelude::*;
 use glam::IVec3;
 
+use crate::material::*;
+use crate::registry::*;
+use crate::lighting::*;
+use crate::generator::*;
+use crate::gravity::*;
+use crate::fluid::*;
 use crate::player::*;
+use crate::region::*;
 use crate::chunk::*;
+use crate::block::*;
 
 
-const PER_FRAME: usize = 1;
-const RENDER_DISTANCE: i32 = 13;
+pub const RENDER_DISTANCE: i32 = 13;
 const UNLOAD_DISTANCE: i32 = 16;
+const VERTICAL_RENDER_DISTANCE: i32 = 4;
+const VERTICAL_UNLOAD_DISTANCE: i32 = 6;
+const TRANSLUCENT_SORT_DISTANCE: i32 = 2;
+// Horizontal chunk distances at which meshes drop to each coarser level of detail
+const LOD_DISTANCES: [i32; 3] = [5, 8, 11];
+// Horizontal chunk distance within which chunks get voxel colliders, chunks further out only get one while a
+// falling body is inside them and since colliders are built with the mesh a fast body can still pass through
+// a chunk before its collider spawns
+const PHYSICS_DISTANCE: i32 = 2;
+// How strongly chunks in front of the camera are loaded before chunks behind it, from 0 to 1
+const VIEW_DIRECTION_BIAS: f32 = 0.3;
 pub const CHUNK_NEIGHBOURS: [IVec3; 6] = [
     IVec3::new(0,  0,  1), // Chunk infront
     IVec3::new(0,  0, -1), // Chunk behind
@@ -35,17 +59,68 @@ pub struct WorldChunks {
 #[derive(Resource, Default)]
 pub struct ChunkEntities {
     pub map: HashMap<IVec3, Entity>,
+    pub translucent: HashMap<IVec3, Entity>,
     pub colliders: HashMap<IVec3, Entity>,
+    pub details: HashMap<IVec3, ChunkDetail>,
 }
 
 
-// Stores each chunk to be loaded
+// Level of detail a chunk mesh is built at and whether the chunk gets a collider
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct ChunkDetail {
+    pub lod: u8,
+    pub collider: bool,
+}
+
+
+// Chunks outside the physics distance that need colliders because physics bodies are inside or above them
+#[derive(Resource, Default)]
+pub struct PhysicsChunks {
+    pub chunks: HashSet<IVec3>,
+}
+
+
+// Stores each chunk to be loaded, nearest and most in view first
 #[derive(Resource, Default)]
 pub struct ChunkQueue {
-    pub queue: VecDeque<IVec3>,
+    pub queue: BinaryHeap<QueuedChunk>,
     pub queued_set: HashSet<IVec3>,
 }
 
+
+// A chunk waiting to load, a lower priority loads sooner
+pub struct QueuedChunk {
+    pub position: IVec3,
+    pub priority: f32,
+}
+
+
+// Chunk meshes and collider points built off the main thread
+pub struct ChunkMeshData {
+    pub meshes: ChunkMeshes,
+    pub points: Vec<Vec3>,
+    pub detail: ChunkDetail,
+}
+
+
+// Tracks chunk generation and meshing running on the async compute pool
+#[derive(Resource, Default)]
+pub struct ChunkTasks {
+    pub generating: HashMap<IVec3, Task<Chunk>>,
+    pub meshing: HashMap<IVec3, Task<ChunkMeshData>>,
+    pub remesh: HashSet<IVec3>,
+    pub ready: VecDeque<(IVec3, ChunkMeshData)>,
+}
+
+
+// Limits how much chunk work is started and finished each frame
+#[derive(Resource)]
+pub struct ChunkLoadSettings {
+    pub max_tasks: usize,
+    pub spawn_budget: usize,
+}
+
+
 // Tracks the player's last chunk
 #[derive(Resource, Default)]
 pub struct PlayerChunk {
@@ -53,26 +128,380 @@ pub struct PlayerChunk {
 }
 
 
-/// Generates a grid of chunks around the player and adds them to the queue.
+// Seed every part of world generation is derived from
+#[derive(Resource, Clone, Copy)]
+pub struct WorldSeed(pub u32);
+
+
+impl WorldSeed {
+    /// Reads the seed from the --seed argument, hashing it when it is not a number.
+    pub fn from_args() -> Self {
+        let arguments: Vec<String> = std::env::args().collect();
+        let seed = arguments
+            .windows(2)
+            .find(|pair| pair[0] == "--seed")
+            .map(|pair| pair[1].as_str());
+
+        match seed {
+            Some(seed) => WorldSeed::from_text(seed),
+            None => WorldSeed(213123),
+        }
+    }
+
+
+    /// Uses a numeric seed as is and hashes any other text.
+    pub fn from_text(seed: &str) -> Self {
+        WorldSeed(seed.parse().unwrap_or_else(|_| hash_text(seed)))
+    }
+}
+
+
+/// Hashes text such as a text seed into a number that stays the same between runs.
+pub fn hash_text(text: &str) -> u32 {
+    text.bytes().fold(2_166_136_261u32, |hash, byte| (hash ^ byte as u32).wrapping_mul(16_777_619))
+}
+
+
+// Vertical limits of the world in blocks
+#[derive(Resource)]
+pub struct WorldBounds {
+    pub min_height: i32,
+    pub max_height: i32,
+}
+
+
+impl WorldBounds {
+    /// Sets default world height limits.
+    pub fn default() -> Self {
+        Self {
+            min_height: -64,
+            max_height: 192,
+        }
+    }
+
+
+    /// Returns the lowest and highest chunk layer inside the world.
+    pub fn chunk_layers(&self) -> (i32, i32) {
+        (
+            self.min_height.div_euclid(CHUNK_SIZE_Y as i32),
+            (self.max_height - 1).div_euclid(CHUNK_SIZE_Y as i32),
+        )
+    }
+
+
+    /// Returns the chunk layer closest to the given layer that lies inside the world.
+    pub fn clamp_layer(&self, chunk_y: i32) -> i32 {
+        let (min_layer, max_layer) = self.chunk_layers();
+        chunk_y.clamp(min_layer, max_layer)
+    }
+}
+
+
+impl ChunkTasks {
+    /// Queues chunks for remeshing, restarting any mesh already in flight.
+    pub fn remesh_all(&mut self, chunk_positions: impl IntoIterator<Item = IVec3>) {
+        for chunk_position in chunk_positions {
+            self.invalidate(chunk_position);
+            self.remesh.insert(chunk_position);
+        }
+    }
+
+
+    /// Restarts meshing of a chunk that was edited while its mesh was in flight or waiting to spawn.
+    pub fn invalidate(&mut self, chunk_position: IVec3) {
+        let ready_count = self.ready.len();
+        self.ready.retain(|(ready_position, _)| *ready_position != chunk_position);
+
+        if self.meshing.remove(&chunk_position).is_some() || self.ready.len() != ready_count {
+            self.remesh.insert(chunk_position);
+        }
+    }
+}
+
+
+impl ChunkDetail {
+    pub const FULL: ChunkDetail = ChunkDetail { lod: 0, collider: true };
+
+
+    /// Returns the detail a chunk needs at its horizontal distance from the players chunk.
+    pub fn at_distance(chunk_position: IVec3, player_chunk: IVec3) -> Self {
+        let distance = (chunk_position - player_chunk).abs();
+        let horizontal_distance = distance.x.max(distance.z);
+
+        Self {
+            lod: LOD_DISTANCES.iter().filter(|lod_distance| horizontal_distance >= **lod_distance).count() as u8,
+            collider: horizontal_distance <= PHYSICS_DISTANCE,
+        }
+    }
+
+
+    /// Returns the detail a chunk needs, adding a collider when physics bodies need one there.
+    pub fn needed(chunk_position: IVec3, player_chunk: IVec3, physics_chunks: &PhysicsChunks) -> Self {
+        let detail = Self::at_distance(chunk_position, player_chunk);
+        Self {
+            collider: detail.collider || physics_chunks.chunks.contains(&chunk_position),
+            ..detail
+        }
+    }
+}
+
+
+impl QueuedChunk {
+    /// Queues a chunk by its distance from the players chunk, shortened for chunks the camera faces.
+    pub fn new(chunk_position: IVec3, player_chunk: IVec3, camera_forward: Vec3) -> Self {
+        let offset = chunk_position - player_chunk;
+        let offset = Vec3::new(offset.x as f32, offset.y as f32, offset.z as f32);
+        let facing = offset.normalize_or_zero().dot(camera_forward);
+
+        Self {
+            position: chunk_position,
+            priority: offset.length() * (1.0 - VIEW_DIRECTION_BIAS * facing),
+        }
+    }
+}
+
+
+// Reversed so the binary heap pops the lowest priority first
+impl Ord for QueuedChunk {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.priority.total_cmp(&self.priority)
+    }
+}
+
+
+impl PartialOrd for QueuedChunk {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+
+impl PartialEq for QueuedChunk {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+
+impl Eq for QueuedChunk {}
+
+
+impl ChunkLoadSettings {
+    /// Sets default chunk loading limits.
+    pub fn default() -> Self {
+        Self {
+            max_tasks: 32,
+            spawn_budget: 4,
+        }
+    }
+}
+
+
+impl WorldChunks {
+    /// Returns the block at a world block position, or None when its chunk is not loaded.
+    pub fn get_block(&self, block_position: IVec3) -> Option<Block> {
+        let (chunk_position, (local_x, local_y, local_z)) = get_block_location(block_position);
+        self.chunks
+            .get(&chunk_position)
+            .map(|chunk| chunk.get(local_x, local_y, local_z))
+    }
+
+
+    /// Sets the block at a world block position, returning the chunk it changed.
+    pub fn set_block(&mut self, block_position: IVec3, block: Block) -> Option<IVec3> {
+        let (chunk_position, (local_x, local_y, local_z)) = get_block_location(block_position);
+        let chunk = self.chunks.get_mut(&chunk_position)?;
+        chunk.set(local_x, local_y, local_z, block);
+
+        Some(chunk_position)
+    }
+
+
+    /// Returns the world positions of the blocks in a loaded chunk that match, skipping chunks whose palette has no match.
+    pub fn find_blocks(&self, chunk_position: IVec3, matches: impl Fn(Block) -> bool) -> Vec<IVec3> {
+        let Some(chunk) = self.chunks.get(&chunk_position) else { return Vec::new(); };
+        let (palette, _bits_per_index, _data) = chunk.storage().raw();
+        if !palette.iter().any(|block| matches(*block)) {
+            return Vec::new();
+        }
+
+        let chunk_origin = chunk_position * IVec3::new(CHUNK_SIZE_X as i32, CHUNK_SIZE_Y as i32, CHUNK_SIZE_Z as i32);
+        (0..CHUNK_VOLUME)
+            .filter(|block_index| matches(chunk.get_at(*block_index)))
+            .map(|block_index| {
+                let (block_x, block_y, block_z) = GET_COORDS[block_index];
+                chunk_origin + IVec3::new(block_x as i32, block_y as i32, block_z as i32)
+            })
+            .collect()
+    }
+
+
+    /// Returns the light level of a channel at a world block position, or None when its chunk is not loaded.
+    pub fn get_light(&self, block_position: IVec3, channel: LightChannel) -> Option<u8> {
+        let (chunk_position, (local_x, local_y, local_z)) = get_block_location(block_position);
+        self.chunks
+            .get(&chunk_position)
+            .map(|chunk| chunk.get_light(Chunk::get_index(local_x, local_y, local_z), channel))
+    }
+
+
+    /// Sets the light level of a channel at a world block position, returning the chunk it changed.
+    pub fn set_light(&mut self, block_position: IVec3, channel: LightChannel, level: u8) -> Option<IVec3> {
+        let (chunk_position, (local_x, local_y, local_z)) = get_block_location(block_position);
+        let chunk = self.chunks.get_mut(&chunk_position)?;
+        chunk.set_light(Chunk::get_index(local_x, local_y, local_z), channel, level);
+
+        Some(chunk_position)
+    }
+
+
+    /// Copies a chunk and its loaded neighbours so it can be meshed on another thread.
+    pub fn snapshot(&self, chunk_position: IVec3) -> WorldChunks {
+        let mut snapshot = WorldChunks::default();
+
+        for neighbor_position in std::iter::once(chunk_position).chain(surrounding_chunks(chunk_position)) {
+            if let Some(chunk) = self.chunks.get(&neighbor_position) {
+                snapshot.chunks.insert(neighbor_position, chunk.clone());
+            }
+        }
+
+        snapshot
+    }
+}
+
+
+/// Returns the 26 chunks sharing a face, edge or corner with the given chunk.
+pub fn surrounding_chunks(chunk_position: IVec3) -> impl Iterator<Item = IVec3> {
+    (-1..=1)
+        .flat_map(|x| (-1..=1).flat_map(move |y| (-1..=1).map(move |z| IVec3::new(x, y, z))))
+        .filter(|offset| *offset != IVec3::ZERO)
+        .map(move |offset| chunk_position + offset)
+}
+
+
+/// Returns the chunk containing the given world position.
+pub fn get_chunk_position(position: Vec3) -> IVec3 {
+    IVec3::new(
+        (position.x / CHUNK_SIZE_X as f32).floor() as i32,
+        (position.y / CHUNK_SIZE_Y as f32).floor() as i32,
+        (position.z / CHUNK_SIZE_Z as f32).floor() as i32,
+    )
+}
+
+
+/// Returns the block containing the given world position.
+pub fn get_block_position(position: Vec3) -> IVec3 {
+    IVec3::new(
+        position.x.floor() as i32,
+        position.y.floor() as i32,
+        position.z.floor() as i32,
+    )
+}
+
+
+/// Returns the chunk containing a block and every neighbouring chunk whose mesh samples it across a border.
+pub fn chunks_sampling_block(block_position: IVec3) -> Vec<IVec3> {
+    let (chunk_position, (local_x, local_y, local_z)) = get_block_location(block_position);
+    let local = [local_x, local_y, local_z];
+    let chunk_size = [CHUNK_SIZE_X, CHUNK_SIZE_Y, CHUNK_SIZE_Z];
+
+    let mut ranges = [(0, 0); 3];
+    for axis in 0..3 {
+        if local[axis] == 0 {
+            ranges[axis].0 = -1;
+        }
+        if local[axis] == chunk_size[axis] - 1 {
+            ranges[axis].1 = 1;
+        }
+    }
+
+    let mut chunk_positions = Vec::new();
+    for x in ranges[0].0..=ranges[0].1 {
+        for y in ranges[1].0..=ranges[1].1 {
+            for z in ranges[2].0..=ranges[2].1 {
+                chunk_positions.push(chunk_position + IVec3::new(x, y, z));
+            }
+        }
+    }
+
+    chunk_positions
+}
+
+
+/// Splits a world block position into its chunk and the position inside that chunk.
+pub fn get_block_location(block_position: IVec3) -> (IVec3, (usize, usize, usize)) {
+    let chunk_size = IVec3::new(CHUNK_SIZE_X as i32, CHUNK_SIZE_Y as i32, CHUNK_SIZE_Z as i32);
+    let local = block_position.rem_euclid(chunk_size);
+
+    (
+        block_position.div_euclid(chunk_size),
+        (local.x as usize, local.y as usize, local.z as usize),
+    )
+}
+
+
+/// Returns the world position of a chunks origin.
+pub fn get_global_position(chunk_position: IVec3) -> Vec3 {
+    Vec3::new(
+        chunk_position.x as f32 * CHUNK_SIZE_X as f32,
+        chunk_position.y as f32 * CHUNK_SIZE_Y as f32,
+        chunk_position.z as f32 * CHUNK_SIZE_Z as f32,
+    )
+}
+
+
+/// Queues missing chunks around the player, reordering the queue whenever the player enters another chunk.
 pub fn queue_chunks(
     player: Query<&Transform, With<Player>>,
+    camera: Query<&Transform, With<Camera3d>>,
     mut player_chunks: ResMut<PlayerChunk>,
     world: ResMut<WorldChunks>,
+    world_bounds: Res<WorldBounds>,
+    chunk_tasks: Res<ChunkTasks>,
     mut chunk_queue: ResMut<ChunkQueue>,
 ) {
     if let Ok(transform) = player.single() {
-        let chunk_x = (transform.translation.x / CHUNK_SIZE_X as f32).floor() as i32;
-        let chunk_z = (transform.translation.z / CHUNK_SIZE_Z as f32).floor() as i32;
-        let current_chunk = IVec3::new(chunk_x, 0, chunk_z);
+        let current_chunk = get_chunk_position(transform.translation);
+        let (min_layer, max_layer) = world_bounds.chunk_layers();
+        let center_y = world_bounds.clamp_layer(current_chunk.y);
+        let camera_forward = camera.single().map(|camera| *camera.forward()).unwrap_or(Vec3::ZERO);
+        let in_load_radius = |chunk_position: IVec3| {
+            (chunk_position.x - current_chunk.x).abs() <= RENDER_DISTANCE
+                && (chunk_position.z - current_chunk.z).abs() <= RENDER_DISTANCE
+                && (chunk_position.y - center_y).abs() <= VERTICAL_RENDER_DISTANCE
+                && chunk_position.y >= min_layer
+                && chunk_position.y <= max_layer
+        };
+
+        // Drop chunks the player moved away from and reorder the rest around the new chunk
+        if player_chunks.last_chunk != current_chunk {
+            let queued: Vec<IVec3> = chunk_queue.queue.drain().map(|queued_chunk| queued_chunk.position).collect();
+            chunk_queue.queued_set.clear();
+
+            for chunk_position in queued.into_iter().filter(|chunk_position| in_load_radius(*chunk_position)) {
+                chunk_queue.queue.push(QueuedChunk::new(chunk_position, current_chunk, camera_forward));
+                chunk_queue.queued_set.insert(chunk_position);
+            }
+        }
 
         player_chunks.last_chunk = current_chunk;
         
         for distance_x in -RENDER_DISTANCE..=RENDER_DISTANCE {
             for distance_z in -RENDER_DISTANCE..=RENDER_DISTANCE {
-                let chunk_position = IVec3::new(chunk_x + distance_x, 0, chunk_z + distance_z);
-                if !world.chunks.contains_key(&chunk_position) && !chunk_queue.queued_set.contains(&chunk_position) {
-                    chunk_queue.queue.push_back(chunk_position);
-                    chunk_queue.queued_set.insert(chunk_position);
+                for distance_y in -VERTICAL_RENDER_DISTANCE..=VERTICAL_RENDER_DISTANCE {
+                    let chunk_y = center_y + distance_y;
+                    if chunk_y < min_layer || chunk_y > max_layer {
+                        continue;
+                    }
+
+                    let chunk_position = IVec3::new(current_chunk.x + distance_x, chunk_y, current_chunk.z + distance_z);
+                    if !world.chunks.contains_key(&chunk_position) 
+                        && !chunk_queue.queued_set.contains(&chunk_position) 
+                        && !chunk_tasks.generating.contains_key(&chunk_position) 
+                    {
+                        chunk_queue.queue.push(QueuedChunk::new(chunk_position, current_chunk, camera_forward));
+                        chunk_queue.queued_set.insert(chunk_position);
+                    }
                 }
             }
         }
@@ -80,76 +509,328 @@ pub fn queue_chunks(
 }
 
 
-/// Loads a select few chunks from the queue per frame.
+/// Starts generation of queued chunks and meshing of changed chunks on the async compute pool.
 pub fn load_chunks(
+    world: Res<WorldChunks>,
+    world_save: Res<WorldSave>,
+    world_seed: Res<WorldSeed>,
+    registry: Res<BlockRegistry>,
+    chunk_generator: Res<ChunkGenerator>,
+    player_chunk: Res<PlayerChunk>,
+    physics_chunks: Res<PhysicsChunks>,
+    load_settings: Res<ChunkLoadSettings>,
+    mut chunk_tasks: ResMut<ChunkTasks>,
+    mut chunk_queue: ResMut<ChunkQueue>,
+) {
+    let task_pool = AsyncComputeTaskPool::get();
+
+    // Load saved chunks or generate queued chunks
+    while chunk_tasks.generating.len() + chunk_tasks.meshing.len() < load_settings.max_tasks {
+        let Some(QueuedChunk { position: chunk_position, .. }) = chunk_queue.queue.pop() else { break; };
+        chunk_queue.queued_set.remove(&chunk_position);
+
+        let save_directory = world_save.directory.clone();
+        let seed = world_seed.0;
+        let registry = registry.clone();
+        let generator = chunk_generator.0.clone();
+        let task = task_pool.spawn(async move {
+            let chunk = match load_chunk(&save_directory, chunk_position) {
+                Ok(Some(chunk)) => chunk,
+                Ok(None) => generator.generate(&chunk_position, seed, &registry),
+                Err(error) => {
+                    warn!("Failed to load chunk {chunk_position}, regenerating it: {error}");
+                    generator.generate(&chunk_position, seed, &registry)
+                }
+            };
+
+            light_new_chunk(chunk_position, chunk, &registry)
+        });
+        chunk_tasks.generating.insert(chunk_position, task);
+    }
+
+    // Mesh chunks whose blocks or neighbours changed, waiting for any mesh already in flight
+    let pending: Vec<IVec3> = chunk_tasks.remesh
+        .iter()
+        .filter(|chunk_position| !chunk_tasks.meshing.contains_key(*chunk_position))
+        .copied()
+        .collect();
+
+    for chunk_position in pending {
+        if chunk_tasks.generating.len() + chunk_tasks.meshing.len() >= load_settings.max_tasks {
+            break;
+        }
+
+        chunk_tasks.remesh.remove(&chunk_position);
+        if !world.chunks.contains_key(&chunk_position) {
+            continue;
+        }
+
+        let snapshot = world.snapshot(chunk_position);
+        let registry = registry.clone();
+        let detail = ChunkDetail::needed(chunk_position, player_chunk.last_chunk, &physics_chunks);
+        let task = task_pool.spawn(async move {
+            ChunkMeshData {
+                meshes: build_lod_mesh(chunk_position, &snapshot, &registry, detail.lod),
+                points: if detail.collider { get_points(&chunk_position, &snapshot, &registry) } else { Vec::new() },
+                detail,
+            }
+        });
+        chunk_tasks.meshing.insert(chunk_position, task);
+    }
+}
+
+
+/// Collects finished generation and meshing tasks.
+pub fn poll_chunk_tasks(
+    mut world: ResMut<WorldChunks>,
+    registry: Res<BlockRegistry>,
+    mut chunk_tasks: ResMut<ChunkTasks>,
+    mut fluid_ticks: ResMut<FluidTicks>,
+    mut gravity_checks: ResMut<GravityChecks>,
+) {
+    let chunk_tasks = &mut *chunk_tasks;
+
+    // Store generated chunks and remesh them along with their loaded neighbours
+    let mut generated = Vec::new();
+    chunk_tasks.generating.retain(|chunk_position, task| {
+        match check_ready(task) {
+            Some(chunk) => {
+                generated.push((*chunk_position, chunk));
+                false
+            }
+            None => true,
+        }
+    });
+
+    for (chunk_position, chunk) in generated {
+        world.chunks.insert(chunk_position, chunk);
+        let relit_chunks = reconcile_chunk_light(&mut world, &registry, chunk_position);
+        chunk_tasks.remesh.insert(chunk_position);
+        update_chunks(chunk_position, &world, chunk_tasks);
+        chunk_tasks.remesh_all(relit_chunks);
+
+        // Fluids saved mid flow, or left flowing at a border that was not loaded yet, carry on
+        fluid_ticks.schedule_loaded_chunk(&world, &registry, chunk_position);
+        gravity_checks.schedule_loaded_chunk(&world, &registry, chunk_position);
+    }
+
+    // Hand finished meshes over to be spawned
+    let ready = &mut chunk_tasks.ready;
+    chunk_tasks.meshing.retain(|chunk_position, task| {
+        match check_ready(task) {
+            Some(mesh_data) => {
+                ready.push_back((*chunk_position, mesh_data));
+                false
+            }
+            None => true,
+        }
+    });
+}
+
+
+/// Spawns or updates chunk entities from finished meshes, limited per frame.
+pub fn spawn_chunks(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    asset_server: Res<AssetServer>,
-    mut world: ResMut<WorldChunks>,
+    chunk_materials: Res<ChunkMaterials>,
+    world: Res<WorldChunks>,
+    player_chunk: Res<PlayerChunk>,
+    physics_chunks: Res<PhysicsChunks>,
+    load_settings: Res<ChunkLoadSettings>,
     mut chunk_entities: ResMut<ChunkEntities>,
-    mut chunk_queue: ResMut<ChunkQueue>,
+    mut chunk_tasks: ResMut<ChunkTasks>,
+) {
+    for _chunks in 0..load_settings.spawn_budget {
+        let Some((chunk_position, mesh_data)) = chunk_tasks.ready.pop_front() else { break; };
+
+        // Chunk was unloaded while it was being meshed
+        if !world.chunks.contains_key(&chunk_position) {
+            continue;
+        }
+
+        // Glabal chunk position
+        let global_position = get_global_position(chunk_position);
+
+        // Add mesh to asset storage
+        let mesh_handle = meshes.add(mesh_data.meshes.opaque);
+
+        // Spawn mesh or swap the mesh of an existing chunk
+        match chunk_entities.map.get(&chunk_position) {
+            Some(entity) => {
+                commands.entity(*entity).insert(Mesh3d(mesh_handle));
+            }
+            None => {
+                let chunk_entity = commands.spawn((
+                    Mesh3d(mesh_handle),
+                    MeshMaterial3d(chunk_materials.opaque.clone()),
+                    Transform::from_translation(global_position),
+                    GlobalTransform::default(),
+                )).id();
+                chunk_entities.map.insert(chunk_position, chunk_entity);
+            }
+        }
+
+        update_translucent_mesh(
+            chunk_position, 
+            mesh_data.meshes.translucent, 
+            &chunk_materials, 
+            &mut chunk_entities, 
+            &mut meshes, 
+            &mut commands,
+        );
+        update_collider(chunk_position, &mesh_data.points, &mut chunk_entities, &mut commands);
+
+        // Meshes started before the player changed chunk or a body moved are rebuilt at the detail now needed
+        chunk_entities.details.insert(chunk_position, mesh_data.detail);
+        if mesh_data.detail != ChunkDetail::needed(chunk_position, player_chunk.last_chunk, &physics_chunks) {
+            chunk_tasks.remesh.insert(chunk_position);
+        }
+    }
+}
+
+
+/// Remeshes chunks whose level of detail or collider changes once the player enters another chunk or physics bodies move.
+pub fn update_chunk_details(
+    player_chunk: Res<PlayerChunk>,
+    physics_chunks: Res<PhysicsChunks>,
+    chunk_entities: Res<ChunkEntities>,
+    mut chunk_tasks: ResMut<ChunkTasks>,
+    mut last_player_chunk: Local<Option<IVec3>>,
 ) {
-    for _chunks in 0..PER_FRAME {
-        if let Some(chunk_position) = chunk_queue.queue.pop_front() {
-            // Build chunk mesh
-            chunk_queue.queued_set.remove(&chunk_position);
-            let prepared_geometry = prepare_geometry(&chunk_position);
-            world.chunks.insert(chunk_position, prepared_geometry);
-            let chunk_mesh = build_mesh(chunk_position, &world);
-
-            // Glabal chunk position
-            let global_position = Vec3::new(
-                chunk_position.x as f32 * CHUNK_SIZE_X as f32,
-                0.0,
-                chunk_position.z as f32 * CHUNK_SIZE_Z as f32,
-            );
-
-            // Add mesh to asset storage
-            let mesh_handle = meshes.add(chunk_mesh);
-            let texture_handle = asset_server.load("texture_atlas.png");
-            let material_handle = materials.add(StandardMaterial {
-                base_color_texture: Some(texture_handle),
-                perceptual_roughness: 0.2,
-                ..Default::default()
-            });
-
-            // Spawn mesh
-            let chunk_entity = commands.spawn((
+    if *last_player_chunk == Some(player_chunk.last_chunk) && !physics_chunks.is_changed() {
+        return;
+    }
+    *last_player_chunk = Some(player_chunk.last_chunk);
+
+    // The old mesh stays visible until its replacement is spawned
+    for (chunk_position, detail) in &chunk_entities.details {
+        if *detail != ChunkDetail::needed(*chunk_position, player_chunk.last_chunk, &physics_chunks) {
+            chunk_tasks.remesh.insert(*chunk_position);
+        }
+    }
+}
+
+
+/// Replaces a chunks translucent mesh, spawning or despawning its entity as translucent blocks appear or disappear.
+pub fn update_translucent_mesh(
+    chunk_position: IVec3,
+    translucent: Option<(Mesh, TranslucentFaces)>,
+    chunk_materials: &ChunkMaterials,
+    chunk_entities: &mut ChunkEntities,
+    meshes: &mut Assets<Mesh>,
+    commands: &mut Commands,
+) {
+    // Chunks without translucent blocks have nothing to draw in the blended pass
+    let Some((mesh, faces)) = translucent else {
+        if let Some(entity) = chunk_entities.translucent.remove(&chunk_position) {
+            commands.entity(entity).despawn();
+        }
+        return;
+    };
+
+    let mesh_handle = meshes.add(mesh);
+
+    match chunk_entities.translucent.get(&chunk_position) {
+        Some(entity) => {
+            commands.entity(*entity).insert((Mesh3d(mesh_handle), faces));
+        }
+        None => {
+            let translucent_entity = commands.spawn((
                 Mesh3d(mesh_handle),
-                MeshMaterial3d(material_handle),
-                Transform::from_translation(global_position),
+                MeshMaterial3d(chunk_materials.translucent.clone()),
+                faces,
+                Transform::from_translation(get_global_position(chunk_position)),
                 GlobalTransform::default(),
             )).id();
+            chunk_entities.translucent.insert(chunk_position, translucent_entity);
+        }
+    }
+}
+
+
+/// Reorders translucent faces back to front whenever the camera enters another block or a mesh changes.
+pub fn sort_translucent_faces(
+    camera_query: Query<&GlobalTransform, With<Camera3d>>,
+    translucent_query: Query<(&Mesh3d, Ref<TranslucentFaces>, &Transform)>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut last_camera_block: Local<Option<IVec3>>,
+) {
+    let Ok(camera_transform) = camera_query.single() else { return; };
+    let camera_position = camera_transform.translation();
+    let camera_block = get_block_position(camera_position);
+    let camera_moved = *last_camera_block != Some(camera_block);
+    *last_camera_block = Some(camera_block);
+
+    let camera_chunk = get_chunk_position(camera_position);
+    for (mesh, faces, transform) in translucent_query.iter() {
+        // Faces in distant chunks are too small on screen for their order to matter
+        let chunk_position = get_chunk_position(transform.translation);
+        let nearby = (chunk_position - camera_chunk).abs().max_element() <= TRANSLUCENT_SORT_DISTANCE;
+        if !(faces.is_changed() || (camera_moved && nearby)) {
+            continue;
+        }
+
+        let Some(mesh) = meshes.get_mut(&mesh.0) else { continue; };
+        let local_camera = camera_position - transform.translation;
+        let mut order: Vec<usize> = (0..faces.centers.len()).collect();
+        order.sort_by(|a, b| {
+            let distance_a = faces.centers[*a].distance_squared(local_camera);
+            let distance_b = faces.centers[*b].distance_squared(local_camera);
+            distance_b.total_cmp(&distance_a)
+        });
+
+        let indices = order.iter().flat_map(|quad| faces.indices[*quad]).collect();
+        mesh.insert_indices(Indices::U32(indices));
+    }
+}
+
+
+/// Replaces a chunks collider, spawning or despawning it as solid blocks appear or disappear.
+pub fn update_collider(
+    chunk_position: IVec3,
+    points: &[Vec3],
+    chunk_entities: &mut ChunkEntities,
+    commands: &mut Commands,
+) {
+    // Chunks without solid blocks have nothing to collide with
+    if points.is_empty() {
+        if let Some(entity) = chunk_entities.colliders.remove(&chunk_position) {
+            commands.entity(entity).despawn();
+        }
+        return;
+    }
 
-            // Gets points for each solid block
-            let points = get_points(&chunk_position, &world);
+    let collider = Collider::voxels_from_points(Vec3::splat(1.0), points);
 
-            // Spawn collider
+    match chunk_entities.colliders.get(&chunk_position) {
+        Some(entity) => {
+            commands.entity(*entity).insert(collider);
+        }
+        None => {
             let collider_entity = commands.spawn((
                 RigidBody::Static,
-                Collider::voxels_from_points(Vec3::splat(1.0), &points),
-                Transform::from_translation(global_position),
+                collider,
+                Transform::from_translation(get_global_position(chunk_position)),
                 Name::new("ChunkCollider"),
             )).id();
-
-            // Add chunk and collider entities to storage
-            chunk_entities.map.insert(chunk_position, chunk_entity);
             chunk_entities.colliders.insert(chunk_position, collider_entity);
-            update_chunks(chunk_position, &world, &chunk_entities, &mut meshes, &mut commands);
         }
     }
 }
 
 
 /// Extracts solid block points from chunk.
-pub fn get_points(chunk_position: &IVec3, world: &WorldChunks) -> Vec<Vec3> {
-    let chunk = world.chunks.get(&chunk_position).unwrap();
+pub fn get_points(chunk_position: &IVec3, world: &WorldChunks, registry: &BlockRegistry) -> Vec<Vec3> {
+    let chunk = world.chunks.get(chunk_position).unwrap();
         let mut points = Vec::new();
+        if chunk.is_empty() {
+            return points;
+        }
+
         for block_index in 0..CHUNK_VOLUME {
             let (block_x, block_y, block_z) = GET_COORDS[block_index];
-            let block = chunk.blocks[Chunk::get_index(block_x, block_y, block_z)];
-            if block.is_solid() {
+            let block = chunk.get_at(block_index);
+            if registry.is_solid(block) {
                 points.push(Vec3::new(block_x as f32, block_y as f32, block_z as f32));
             }
         }
@@ -163,53 +844,120 @@ pub fn unload_chunks(
     mut commands: Commands,
     player: Query<&Transform, With<Player>>,
     mut world: ResMut<WorldChunks>,
+    world_bounds: Res<WorldBounds>,
     mut chunk_entities: ResMut<ChunkEntities>,
+    mut chunk_tasks: ResMut<ChunkTasks>,
+    mut world_save: ResMut<WorldSave>,
 ) {
     if let Ok(player_transform) = player.single() {
-        let player_chunk_x = (player_transform.translation.x / CHUNK_SIZE_X as f32).floor() as i32;
-        let player_chunk_z = (player_transform.translation.z / CHUNK_SIZE_Z as f32).floor() as i32;
+        let player_chunk = get_chunk_position(player_transform.translation);
+        let center_y = world_bounds.clamp_layer(player_chunk.y);
+        let out_of_range = |chunk_position: &IVec3| {
+            (chunk_position.x - player_chunk.x).abs() > UNLOAD_DISTANCE 
+                || (chunk_position.z - player_chunk.z).abs() > UNLOAD_DISTANCE 
+                || (chunk_position.y - center_y).abs() > VERTICAL_UNLOAD_DISTANCE
+        };
 
-        world.chunks.retain(|chunk_position, _chunk| {
-            let distance_x = chunk_position.x - player_chunk_x;
-            let distance_z = chunk_position.z - player_chunk_z;
+        // Dropping a task cancels it
+        chunk_tasks.generating.retain(|chunk_position, _task| !out_of_range(chunk_position));
 
-            if distance_x.abs() > UNLOAD_DISTANCE || distance_z.abs() > UNLOAD_DISTANCE {
+        // Edited chunks are written to disk before they are dropped, a failed save keeps them loaded to retry
+        let unloading: Vec<IVec3> = world.chunks.keys().filter(|chunk_position| out_of_range(chunk_position)).copied().collect();
+        save_unloading_chunks(&world, &unloading, &mut world_save);
+
+        world.chunks.retain(|chunk_position, _chunk| {
+            if out_of_range(chunk_position) && !world_save.modified.contains(chunk_position) {
                 if let Some(entity) = chunk_entities.map.remove(chunk_position) {
                     commands.entity(entity).despawn();
                 }
+                if let Some(entity) = chunk_entities.translucent.remove(chunk_position) {
+                    commands.entity(entity).despawn();
+                }
                 if let Some(entity) = chunk_entities.colliders.remove(chunk_position) {
                     commands.entity(entity).despawn();
                 }
+                chunk_entities.details.remove(chunk_position);
                 false
             } 
             else {
                 true
             }
         });
+
+        chunk_tasks.meshing.retain(|chunk_position, _task| world.chunks.contains_key(chunk_position));
+        chunk_tasks.remesh.retain(|chunk_position| world.chunks.contains_key(chunk_position));
     }
 }
 
 
-/// Updates chunk mesh visibility around newly spawned chunk.
-pub fn update_chunks(
+/// Rebuilds an edited chunks mesh and collider immediately and queues its neighbours for remeshing.
+pub fn rebuild_chunk(
     chunk_position: IVec3,
     world: &WorldChunks,
-    chunk_entities: &ChunkEntities,
-    meshes: &mut ResMut<Assets<Mesh>>,
+    registry: &BlockRegistry,
+    chunk_materials: &ChunkMaterials,
+    chunk_entities: &mut ChunkEntities,
+    chunk_tasks: &mut ChunkTasks,
+    meshes: &mut Assets<Mesh>,
     commands: &mut Commands,
 ) {
-    for neighbour in CHUNK_NEIGHBOURS.iter() {
-        let neighbor_position = chunk_position + *neighbour;
+    // Rebuild mesh with updated chunk at its current detail, chunks without an entity yet get meshed with the rest
+    chunk_tasks.invalidate(chunk_position);
+    let detail = chunk_entities.details.get(&chunk_position).copied().unwrap_or(ChunkDetail::FULL);
+    match chunk_entities.map.get(&chunk_position) {
+        Some(entity) => {
+            let chunk_meshes = build_lod_mesh(chunk_position, world, registry, detail.lod);
+            let mesh_handle = meshes.add(chunk_meshes.opaque);
+            commands.entity(*entity).insert(Mesh3d(mesh_handle));
+            update_translucent_mesh(chunk_position, chunk_meshes.translucent, chunk_materials, chunk_entities, meshes, commands);
+        }
+        None => {
+            chunk_tasks.remesh.insert(chunk_position);
+        }
+    }
 
-        if world.chunks.contains_key(&neighbor_position) {
-            let neighbor_mesh = build_mesh(neighbor_position, world);
-            let mesh_handle = meshes.add(neighbor_mesh);
+    // Update collider, chunks beyond the physics distance have none
+    let points = if detail.collider { get_points(&chunk_position, world, registry) } else { Vec::new() };
+    update_collider(chunk_position, &points, chunk_entities, commands);
 
-            if let Some(entity) = chunk_entities.map.get(&neighbor_position) {
-                commands.entity(*entity).insert(Mesh3d(mesh_handle));
-            }
+    update_chunks(chunk_position, world, chunk_tasks);
+}
+
+
+/// Queues the loaded neighbours of a changed chunk for remeshing, including diagonal ones whose occlusion it affects.
+pub fn update_chunks(
+    chunk_position: IVec3,
+    world: &WorldChunks,
+    chunk_tasks: &mut ChunkTasks,
+) {
+    for neighbor_position in surrounding_chunks(chunk_position) {
+        if world.chunks.contains_key(&neighbor_position) {
+            chunk_tasks.remesh.insert(neighbor_position);
         }
     }
 }
 
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    #[test]
+    fn numeric_seeds_are_used_as_is() {
+        assert_eq!(WorldSeed::from_text("42").0, 42);
+        assert_eq!(WorldSeed::from_text("4294967295").0, u32::MAX);
+    }
+
+
+    #[test]
+    fn text_seeds_hash_to_the_same_value_every_run() {
+        // FNV-1a values, a change here would move every world generated from a text seed
+        assert_eq!(hash_text(""), 0x811C_9DC5);
+        assert_eq!(hash_text("hello"), 0x4F9F_2CAB);
+        assert_eq!(WorldSeed::from_text("hello").0, 0x4F9F_2CAB);
+
+        // Numbers too large for a seed fall back to hashing their text
+        assert_eq!(WorldSeed::from_text("4294967296").0, hash_text("4294967296"));
+    }
+}